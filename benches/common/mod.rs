@@ -0,0 +1,131 @@
+//! Reproducible synthetic schedules shared by the benchmarks.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+
+use chrono_tz::Tz;
+use rust_test::airport::{Airport, AirportsContainer};
+use rust_test::flight::{Cabin, FlightDTO, UNLIMITED_SEATS};
+
+pub const DATE: &str = "2024-01-14";
+
+// SplitMix64: tiny, dependency-free and identical on every machine.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low)
+    }
+}
+
+/// A single synthetic flight: origin, destination, departure and arrival as
+/// minutes after midnight on `DATE`, and distance.
+pub struct SyntheticFlight {
+    pub from: usize,
+    pub to: usize,
+    pub depart: u64,
+    pub arrive: u64,
+    pub distance: i32,
+}
+
+/// `airports * flights_per_airport` flights between random airport pairs,
+/// all departing on `DATE`.
+pub fn flights(airports: usize, flights_per_airport: usize, seed: u64) -> Vec<SyntheticFlight> {
+    let mut rng = Rng::new(seed);
+    let mut flights = vec![];
+    for from in 0..airports {
+        for _ in 0..flights_per_airport {
+            let mut to = rng.range(0, airports as u64) as usize;
+            if to == from {
+                to = (to + 1) % airports;
+            }
+            let depart = rng.range(0, 20 * 60);
+            let arrive = depart + rng.range(45, 4 * 60);
+            flights.push(SyntheticFlight {
+                from,
+                to,
+                depart,
+                arrive,
+                distance: rng.range(100, 3000) as i32,
+            });
+        }
+    }
+    flights
+}
+
+pub fn container(airports: usize, flights_per_airport: usize, seed: u64) -> AirportsContainer {
+    let container = AirportsContainer::new();
+    for id in 0..airports {
+        container.add_airport(Airport {
+            id,
+            name: format!("A{}", id),
+            latitude: 0.0,
+            longitude: 0.0,
+            timezone: Tz::UTC,
+            outgoing: BTreeMap::new(),
+        });
+    }
+    for (flight_id, flight) in flights(airports, flights_per_airport, seed)
+        .into_iter()
+        .enumerate()
+    {
+        container.add_flight(FlightDTO {
+            flight_id,
+            from: flight.from,
+            to: flight.to,
+            cost: flight.distance as i64,
+            currency: "USD".to_string(),
+            distance: flight.distance,
+            cabin: Cabin::Economy,
+            seats: UNLIMITED_SEATS,
+            airline: String::new(),
+            flight_number: String::new(),
+            codeshares: vec![],
+            departure_date: datetime(flight.depart),
+            duration_minutes: None,
+            arrival_date: datetime(flight.arrive),
+        });
+    }
+    container
+}
+
+fn datetime(minutes: u64) -> String {
+    format!("{} {:02}:{:02}:00", DATE, minutes / 60, minutes % 60)
+}
+
+/// The same flights rendered in the BTS on-time CSV layout read by
+/// `CsvFlightImporter`.
+pub fn bts_csv(airports: usize, flights_per_airport: usize, seed: u64) -> String {
+    const COLUMNS: usize = 64;
+    let mut out = (0..COLUMNS)
+        .map(|i| format!("c{}", i))
+        .collect::<Vec<_>>()
+        .join(",");
+    out.push('\n');
+    for flight in flights(airports, flights_per_airport, seed) {
+        let mut row = vec![String::new(); COLUMNS];
+        row[5] = DATE.to_string();
+        row[20] = (flight.from + 10000).to_string();
+        row[23] = format!("A{}", flight.from);
+        row[29] = (flight.to + 10000).to_string();
+        row[32] = format!("A{}", flight.to);
+        row[38] = format!("{:02}{:02}", flight.depart / 60, flight.depart % 60);
+        row[49] = format!("{:02}{:02}", (flight.arrive / 60) % 24, flight.arrive % 60);
+        row[63] = flight.distance.to_string();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
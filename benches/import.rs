@@ -0,0 +1,34 @@
+use std::fs;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use rust_test::airport::AirportsContainer;
+use rust_test::import::{CsvFlightImporter, FlightImporter};
+
+mod common;
+
+const SEED: u64 = 42;
+const AIRPORTS: usize = 200;
+const FLIGHTS_PER_AIRPORT: usize = 50;
+
+fn import_throughput(c: &mut Criterion) {
+    let path = std::env::temp_dir().join("rust-flight-search-bench.csv");
+    fs::write(&path, common::bts_csv(AIRPORTS, FLIGHTS_PER_AIRPORT, SEED)).unwrap();
+    let path = path.to_str().unwrap().to_string();
+
+    let mut group = c.benchmark_group("import");
+    group.throughput(Throughput::Elements(
+        (AIRPORTS * FLIGHTS_PER_AIRPORT) as u64,
+    ));
+    group.bench_function("csv", |b| {
+        b.iter(|| {
+            let airports = AirportsContainer::new();
+            let importer = CsvFlightImporter::new(&airports);
+            black_box(importer.import_flights(&path).unwrap())
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, import_throughput);
+criterion_main!(benches);
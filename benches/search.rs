@@ -0,0 +1,134 @@
+use std::thread;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use rust_test::search::{PathState, Search, SearchQuery};
+
+mod common;
+
+const SEED: u64 = 42;
+const GRAPH_SIZES: [usize; 2] = [50, 200];
+const FLIGHTS_PER_AIRPORT: usize = 20;
+// Enough departures per airport for first legs to dominate a search.
+const HUB_FLIGHTS_PER_AIRPORT: usize = 2000;
+// Searches running at once in the `concurrent` group.
+const CONCURRENT_SEARCHES: usize = 8;
+
+fn query(hops: usize) -> SearchQuery {
+    SearchQuery {
+        from: 0,
+        to: 1,
+        date: common::DATE.to_string(),
+        hops,
+        results: 10,
+        ..Default::default()
+    }
+}
+
+fn seed_and_single_leg(c: &mut Criterion) {
+    c.bench_function("seed_and_single_leg", |b| {
+        b.iter(|| {
+            let search = Search::new(common::container(50, FLIGHTS_PER_AIRPORT, SEED));
+            black_box(search.find_or_empty(query(1)))
+        })
+    });
+}
+
+fn multi_hop(c: &mut Criterion) {
+    let mut group = c.benchmark_group("multi_hop");
+    for airports in GRAPH_SIZES {
+        let search = Search::new(common::container(airports, FLIGHTS_PER_AIRPORT, SEED));
+        for hops in [2, 3] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}_hop", hops), airports),
+                &hops,
+                |b, &hops| b.iter(|| black_box(search.find_or_empty(query(hops)))),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn parallel_seeding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parallel_seeding");
+    let container = common::container(20, HUB_FLIGHTS_PER_AIRPORT, SEED);
+    let sequential = Search::new(container.clone());
+    let parallel = Search::new(container).with_parallel_seeding(true);
+    let ids = |search: &Search| -> Vec<Vec<usize>> {
+        search
+            .find_or_empty(query(2))
+            .iter()
+            .map(|x| x.legs().iter().map(|leg| leg.flight_id).collect())
+            .collect()
+    };
+    assert_eq!(ids(&sequential), ids(&parallel));
+    for (name, search) in [("sequential", &sequential), ("parallel", &parallel)] {
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(search.find_or_empty(query(2))))
+        });
+    }
+    group.finish();
+}
+
+fn cheapest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cheapest");
+    let search = Search::new(common::container(200, FLIGHTS_PER_AIRPORT, SEED));
+    let best = SearchQuery {
+        results: 1,
+        ..query(3)
+    };
+    let legs = |state: &PathState| -> Vec<usize> {
+        state.legs().iter().map(|leg| leg.flight_id).collect()
+    };
+    assert_eq!(
+        search.find_or_empty(best.clone()).first().map(legs),
+        search
+            .find_cheapest(0, 1, common::DATE, 3)
+            .as_ref()
+            .map(legs)
+    );
+    group.bench_function("find", |b| {
+        b.iter(|| black_box(search.find_or_empty(best.clone())))
+    });
+    group.bench_function("find_cheapest", |b| {
+        b.iter(|| black_box(search.find_cheapest(0, 1, common::DATE, 3)))
+    });
+    group.finish();
+}
+
+fn concurrent(c: &mut Criterion) {
+    let mut group = c.benchmark_group("concurrent");
+    let container = common::container(200, FLIGHTS_PER_AIRPORT, SEED);
+    let locked = Search::new(container.clone());
+    let snapshot = Search::new(container).with_read_view(true);
+    let ids = |search: &Search| -> Vec<Vec<usize>> {
+        search
+            .find_or_empty(query(3))
+            .iter()
+            .map(|x| x.legs().iter().map(|leg| leg.flight_id).collect())
+            .collect()
+    };
+    assert_eq!(ids(&locked), ids(&snapshot));
+    for (name, search) in [("locked", &locked), ("snapshot", &snapshot)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                thread::scope(|scope| {
+                    for _ in 0..CONCURRENT_SEARCHES {
+                        scope.spawn(|| black_box(search.find_or_empty(query(3))));
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    seed_and_single_leg,
+    multi_hop,
+    parallel_seeding,
+    cheapest,
+    concurrent
+);
+criterion_main!(benches);
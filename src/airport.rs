@@ -1,12 +1,124 @@
-use chrono::{Duration, NaiveDateTime};
-use std::collections::{BTreeMap, BinaryHeap};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
-use crate::flight::{self, FlightDTO};
+use crate::flight::{self, FlightDTO, UNLIMITED_SEATS};
 use flight::{FlightEdge, FlightEdgeWrapper, FlightsContainer};
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+/// Zero-based positions of the fields `load_airports_from_csv_with_columns`
+/// reads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AirportColumnMap {
+    pub id: usize,
+    pub name: usize,
+    /// IANA timezone name such as "America/New_York". Airports without a
+    /// valid one are placed in UTC.
+    pub timezone: Option<usize>,
+    /// Decimal degrees, north positive. Airports without one are placed at
+    /// 0.
+    pub latitude: Option<usize>,
+    /// Decimal degrees, east positive.
+    pub longitude: Option<usize>,
+}
+
+impl Default for AirportColumnMap {
+    fn default() -> Self {
+        AirportColumnMap {
+            id: 0,
+            name: 3,
+            timezone: None,
+            latitude: None,
+            longitude: None,
+        }
+    }
+}
+
+/// Minimum time between arriving on one flight and departing on the next.
+pub const MIN_CONNECTION_MINUTES: i64 = 15;
+
+/// Mean Earth radius used for great-circle distances.
+pub const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationWarning {
+    /// A flight is stored in an `outgoing` bucket whose key differs from the
+    /// flight's `depart_at`, so range queries would see it at the wrong time.
+    BucketKeyMismatch {
+        airport_id: usize,
+        flight_id: usize,
+        key: NaiveDateTime,
+        depart_at: NaiveDateTime,
+    },
+    /// Every outbound flight leaves before any inbound flight could make
+    /// the minimum connection, so the airport can never be connected
+    /// through.
+    NoPossibleConnection {
+        airport_id: usize,
+        first_arrival: NaiveDateTime,
+        last_departure: NaiveDateTime,
+    },
+}
+
+/// How much data a container holds, from `AirportsContainer::stats`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ContainerStats {
+    pub num_airports: usize,
+    pub num_flights: usize,
+    /// First departure in local time at its airport, None without flights.
+    pub earliest_departure: Option<NaiveDateTime>,
+    /// Last departure in local time at its airport.
+    pub latest_departure: Option<NaiveDateTime>,
+    /// Distinct airports each airport has a direct flight to, on average.
+    pub avg_out_degree: f64,
+}
+
+/// A disagreement between `FlightsContainer` and the airports' `outgoing`
+/// indexes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// The flight is in the container but in no airport's `outgoing`.
+    NotIndexed { flight_id: usize },
+    /// The flight is indexed more than once.
+    IndexedRepeatedly {
+        flight_id: usize,
+        occurrences: usize,
+    },
+    /// The flight is indexed under an airport other than its origin, or
+    /// under a key other than its departure.
+    Misplaced {
+        flight_id: usize,
+        airport_id: usize,
+        key: NaiveDateTime,
+    },
+    /// An airport indexes a flight the container does not have.
+    Orphaned { flight_id: usize, airport_id: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    AddFlight,
+    RemoveFlight,
+    UpdateFlight,
+    AddAirport,
+    RemoveAirport,
+}
+
+#[derive(Clone, Debug)]
+pub struct MutationEvent {
+    pub kind: MutationKind,
+    pub airport_id: usize,
+    pub flight_id: Option<usize>,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub type MutationHook = Arc<dyn Fn(&MutationEvent) + Send + Sync>;
 
 pub enum AirportAccess {
     Read(Arc<RwLock<Airport>>),
@@ -14,10 +126,39 @@ pub enum AirportAccess {
     None,
 }
 
-#[derive(Clone)]
 pub struct AirportsContainer {
     pub airports: DashMap<usize, Arc<RwLock<Airport>>>,
     pub flights_container: Arc<RwLock<flight::FlightsContainer>>,
+    on_mutation: Option<MutationHook>,
+    // `hub_scores` as of the data version it was computed at.
+    hub_scores: RwLock<Option<(u64, HashMap<usize, f64>)>>,
+    // Upper-cased airport code (the airport's name) to id.
+    codes: DashMap<String, usize>,
+    // Bumped on every mutation; see `data_version`. Shared with clones,
+    // which share the airports and flights too.
+    version: Arc<AtomicU64>,
+}
+
+impl Clone for AirportsContainer {
+    // The clone gets its own airport map, so cached scores are not shared.
+    // It shares the version counter, so a change through either handle
+    // makes what the other derived from the graph stale.
+    fn clone(&self) -> Self {
+        AirportsContainer {
+            airports: self.airports.clone(),
+            flights_container: self.flights_container.clone(),
+            on_mutation: self.on_mutation.clone(),
+            hub_scores: RwLock::new(None),
+            codes: self.codes.clone(),
+            version: self.version.clone(),
+        }
+    }
+}
+
+impl Default for AirportsContainer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AirportsContainer {
@@ -25,6 +166,34 @@ impl AirportsContainer {
         AirportsContainer {
             airports: DashMap::new(),
             flights_container: Arc::new(RwLock::new(FlightsContainer::new())),
+            on_mutation: None,
+            hub_scores: RwLock::new(None),
+            codes: DashMap::new(),
+            version: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Registers a hook called after every flight or airport is added or
+    /// removed, e.g. to keep an audit log or invalidate external caches.
+    pub fn set_mutation_hook(&mut self, hook: MutationHook) {
+        self.on_mutation = Some(hook);
+    }
+
+    /// Counter that changes whenever a flight or airport is added or
+    /// removed, so anything derived from the graph can tell it is stale.
+    pub fn data_version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    fn notify(&self, kind: MutationKind, airport_id: usize, flight_id: Option<usize>) {
+        self.version.fetch_add(1, Ordering::AcqRel);
+        if let Some(hook) = &self.on_mutation {
+            hook(&MutationEvent {
+                kind,
+                airport_id,
+                flight_id,
+                timestamp: Utc::now(),
+            });
         }
     }
 
@@ -42,6 +211,72 @@ impl AirportsContainer {
                 .unwrap()
                 .remove_flight(flight_id, flight.depart_at);
         }
+        let _ = self
+            .flights_container
+            .write()
+            .unwrap()
+            .remove_flight(flight_id);
+        self.notify(MutationKind::RemoveFlight, from, Some(flight_id));
+    }
+
+    /// Changes a flight's price, returning false if there is no such
+    /// flight. The edge is replaced rather than mutated, so itineraries
+    /// already found keep the price they were found at.
+    pub fn update_flight_cost(&self, flight_id: usize, new_cost: i64) -> bool {
+        self.replace_flight(flight_id, |flight| FlightEdge {
+            cost: new_cost,
+            ..flight.clone()
+        })
+        .is_some()
+    }
+
+    /// Takes `n` seats on a flight, as a booking would, and returns how many
+    /// are left, or None if there is no such flight. A flight with no seats
+    /// left is never searched again. Flights with `UNLIMITED_SEATS` are
+    /// unaffected.
+    pub fn decrement_seats(&self, flight_id: usize, n: u32) -> Option<u32> {
+        let updated = self.replace_flight(flight_id, |flight| {
+            let seats = match flight.seats {
+                UNLIMITED_SEATS => UNLIMITED_SEATS,
+                seats => seats.saturating_sub(n),
+            };
+            FlightEdge {
+                seats,
+                ..flight.clone()
+            }
+        })?;
+        Some(updated.seats)
+    }
+
+    // Swaps a flight's edge for `update` of it, keeping the departure index
+    // in step. The edge is replaced rather than mutated, so itineraries
+    // already found keep the flight as it was. Flights missing from the
+    // departure index are left alone rather than indexed again.
+    fn replace_flight(
+        &self,
+        flight_id: usize,
+        update: impl FnOnce(&FlightEdge) -> FlightEdge,
+    ) -> Option<Arc<FlightEdge>> {
+        let mut flights = self.flights_container.write().unwrap();
+        let flight = flights.get_flight(flight_id)?;
+        let from = flight.from.read().unwrap().id;
+        if !self
+            .airports
+            .get(&from)
+            .is_some_and(|x| x.read().unwrap().departs(flight_id, flight.depart_at))
+        {
+            return None;
+        }
+        let updated = flights.add_flight(update(&flight));
+        drop(flights);
+        if let Some(airport) = self.airports.get(&from) {
+            // Re-pushing restores the heap's cost order for the slot.
+            let mut airport = airport.write().unwrap();
+            airport.remove_flight(flight_id, updated.depart_at);
+            airport.add_flight(updated.clone(), updated.depart_at);
+        }
+        self.notify(MutationKind::UpdateFlight, from, Some(flight_id));
+        Some(updated)
     }
 
     pub fn add_flight(&self, flight: FlightDTO) {
@@ -50,20 +285,18 @@ impl AirportsContainer {
             AirportAccess::Read(airport) => airport,
             _ => return,
         };
-        match airport_from {
-            AirportAccess::Write(airport) => {
-                let flight_edge = flight.to_edge(airport.clone(), airport_to);
-                let flight_ref = self
-                    .flights_container
-                    .write()
-                    .unwrap()
-                    .add_flight(flight_edge.clone());
-                airport
-                    .write()
-                    .unwrap()
-                    .add_flight(flight_ref, flight.departure_date());
-            }
-            _ => {}
+        if let AirportAccess::Write(airport) = airport_from {
+            let flight_edge = flight.to_edge(airport.clone(), airport_to);
+            let flight_ref = self
+                .flights_container
+                .write()
+                .unwrap()
+                .add_flight(flight_edge.clone());
+            // Bucket under the edge's own departure so range queries and the
+            // edge never disagree (e.g. on a 24:00 rollover).
+            let depart_at = flight_ref.depart_at;
+            airport.write().unwrap().add_flight(flight_ref, depart_at);
+            self.notify(MutationKind::AddFlight, flight.from, Some(flight.flight_id));
         }
     }
     pub fn get_airport_ref(&self, airport_id: usize, write: bool) -> AirportAccess {
@@ -83,7 +316,180 @@ impl AirportsContainer {
         }
     }
     pub fn add_airport(&self, airport: Airport) {
+        let airport_id = airport.id;
+        self.codes.insert(airport.name.to_uppercase(), airport_id);
         self.airports.insert(airport.id, Arc::new(RwLock::new(airport.clone())));
+        self.notify(MutationKind::AddAirport, airport_id, None);
+    }
+
+    /// Removes an airport together with every flight departing from or
+    /// arriving at it.
+    pub fn remove_airport(&self, airport_id: usize) {
+        let Some((_, airport)) = self.airports.remove(&airport_id) else {
+            return;
+        };
+        let code = airport.read().unwrap().name.to_uppercase();
+        self.codes.remove_if(&code, |_, id| *id == airport_id);
+        let mut flights = self.flights_container.write().unwrap();
+        let touching: Vec<Arc<FlightEdge>> = flights
+            .flights
+            .iter()
+            .filter(|x| {
+                x.from.read().unwrap().id == airport_id || x.to.read().unwrap().id == airport_id
+            })
+            .map(|x| x.clone())
+            .collect();
+        for flight in touching {
+            let from = flight.from.read().unwrap().id;
+            if let Some(airport) = self.airports.get(&from) {
+                airport
+                    .write()
+                    .unwrap()
+                    .remove_flight(flight.flight_id, flight.depart_at);
+            }
+            let _ = flights.remove_flight(flight.flight_id);
+        }
+        drop(flights);
+        self.notify(MutationKind::RemoveAirport, airport_id, None);
+    }
+
+    /// Outgoing flights of one airport, in departure order. Together with
+    /// `import_airport_schedule` this allows replacing a single airport's
+    /// schedule without reloading the graph.
+    pub fn export_airport_schedule(&self, airport_id: usize) -> Vec<FlightDTO> {
+        let Some(airport) = self.airports.get(&airport_id) else {
+            return vec![];
+        };
+        let airport = airport.read().unwrap();
+        let mut schedule = vec![];
+        for heap in airport.outgoing.values() {
+            let mut flights: Vec<FlightDTO> = heap.iter().map(|x| x.flight().to_dto()).collect();
+            flights.sort_by_key(|x| x.flight_id);
+            schedule.extend(flights);
+        }
+        schedule
+    }
+
+    /// Replaces every outgoing flight of `airport_id` with `schedule`.
+    /// Flights not departing from the airport are skipped. Returns the
+    /// number of flights added.
+    pub fn import_airport_schedule(&self, airport_id: usize, schedule: Vec<FlightDTO>) -> usize {
+        let Some(airport) = self.airports.get(&airport_id).map(|x| x.clone()) else {
+            return 0;
+        };
+        let outgoing: Vec<Arc<FlightEdge>> = airport
+            .read()
+            .unwrap()
+            .outgoing
+            .values()
+            .flat_map(|heap| heap.iter().map(|x| x.flight().clone()))
+            .collect();
+        for flight in outgoing {
+            airport
+                .write()
+                .unwrap()
+                .remove_flight(flight.flight_id, flight.depart_at);
+            let _ = self
+                .flights_container
+                .write()
+                .unwrap()
+                .remove_flight(flight.flight_id);
+            self.notify(
+                MutationKind::RemoveFlight,
+                airport_id,
+                Some(flight.flight_id),
+            );
+        }
+        let mut added = 0;
+        for flight in schedule {
+            if flight.from != airport_id {
+                tracing::warn!(
+                    airport_id,
+                    flight_id = flight.flight_id,
+                    "skipping flight departing from another airport"
+                );
+                continue;
+            }
+            if self.has_airport(flight.to) {
+                self.add_flight(flight);
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Writes every airport and flight to `path` in a compact binary form
+    /// that `load_snapshot` reads back far faster than re-importing CSV.
+    pub fn save_snapshot(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut airports: Vec<AirportDTO> = self
+            .airports
+            .iter()
+            .map(|entry| entry.read().unwrap().to_dto())
+            .collect();
+        airports.sort_by_key(|x| x.id);
+        let mut flights: Vec<FlightDTO> = self
+            .flights_container
+            .read()
+            .unwrap()
+            .flights
+            .iter()
+            .map(|entry| entry.to_dto())
+            .collect();
+        flights.sort_by_key(|x| x.flight_id);
+        let writer = BufWriter::new(File::create(path)?);
+        bincode::serialize_into(writer, &Snapshot { airports, flights })?;
+        Ok(())
+    }
+
+    /// Adds the airports and flights saved by `save_snapshot`, keeping
+    /// their ids. Departure indexes are rebuilt as the flights are added.
+    pub fn load_snapshot(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let reader = BufReader::new(File::open(path)?);
+        let snapshot: Snapshot = bincode::deserialize_from(reader)?;
+        for airport in &snapshot.airports {
+            self.add_airport(airport.to_airport());
+        }
+        for flight in snapshot.flights {
+            self.add_flight(flight);
+        }
+        Ok(())
+    }
+
+    /// A read-only copy of every airport's departures that searches can use
+    /// to find connections without taking the airports' locks. First legs
+    /// are still read from the airports, and building and filtering states
+    /// still locks the airports of each flight. It does not follow later
+    /// changes; compare `SearchView::version` with `data_version` to tell it
+    /// is stale.
+    pub fn read_view(&self) -> SearchView {
+        let version = self.data_version();
+        let mut departures: HashMap<usize, Vec<ViewFlight>> = HashMap::new();
+        for entry in self.airports.iter() {
+            // Read from the departure index, as searches do, rather than
+            // `flights_container`.
+            let (from, flights): (usize, Vec<Arc<FlightEdge>>) = {
+                let airport = entry.read().unwrap();
+                let flights = airport
+                    .outgoing
+                    .values()
+                    .flat_map(|heap| heap.iter().map(|x| x.flight()))
+                    .collect();
+                (airport.id, flights)
+            };
+            let mut flights: Vec<ViewFlight> = flights
+                .into_iter()
+                .map(|flight| {
+                    let to = flight.to.read().unwrap().id;
+                    ViewFlight { flight, from, to }
+                })
+                .collect();
+            flights.sort_by_key(|x| (x.flight.depart_utc, x.flight.flight_id));
+            departures.insert(from, flights);
+        }
+        SearchView {
+            departures,
+            version,
+        }
     }
 
     pub fn has_airport(&self, airport_id: usize) -> bool {
@@ -93,25 +499,335 @@ impl AirportsContainer {
         false
     }
 
+    /// Great-circle distance between two airports, or None if either is
+    /// unknown.
+    pub fn haversine_distance_km(&self, a: usize, b: usize) -> Option<f64> {
+        let position = |id: usize| {
+            let airport = self.airports.get(&id)?;
+            let airport = airport.read().unwrap();
+            Some((
+                airport.latitude.to_radians(),
+                airport.longitude.to_radians(),
+            ))
+        };
+        let ((lat_a, lon_a), (lat_b, lon_b)) = (position(a)?, position(b)?);
+        let h = ((lat_b - lat_a) / 2.0).sin().powi(2)
+            + lat_a.cos() * lat_b.cos() * ((lon_b - lon_a) / 2.0).sin().powi(2);
+        Some(2.0 * EARTH_RADIUS_KM * h.sqrt().asin())
+    }
+
+    /// Id of the airport whose code (its name, e.g. "JFK") is exactly
+    /// `code`, ignoring case. If several share a code, the last added wins.
+    pub fn get_airport_by_code(&self, code: &str) -> Option<usize> {
+        self.codes.get(&code.to_uppercase()).map(|id| *id)
+    }
+
+    /// Ids of all airports whose name contains `query`, ignoring case.
+    /// Ambiguous queries return every match, sorted by id.
+    pub fn resolve_name(&self, query: &str) -> Vec<usize> {
+        let query = query.to_lowercase();
+        let mut ids: Vec<usize> = self
+            .airports
+            .iter()
+            .filter(|entry| entry.read().unwrap().name.to_lowercase().contains(&query))
+            .map(|entry| *entry.key())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Airports best matching a partly typed or misspelled name, as `(id,
+    /// name, score)` with the best match first and at most `limit` of them.
+    /// Scores run from 0 to 1 and use Jaro-Winkler similarity, ignoring
+    /// case, against the whole name or any one word of it, whichever is
+    /// closer.
+    pub fn search_airports(&self, query: &str, limit: usize) -> Vec<(usize, String, f64)> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return vec![];
+        }
+        let mut matches: Vec<(usize, String, f64)> = self
+            .airports
+            .iter()
+            .map(|entry| {
+                let airport = entry.read().unwrap();
+                let name = airport.name.to_lowercase();
+                let score = name
+                    .split_whitespace()
+                    .chain([name.as_str()])
+                    .map(|x| strsim::jaro_winkler(&query, x))
+                    .fold(0.0, f64::max);
+                (airport.id, airport.name.clone(), score)
+            })
+            .collect();
+        matches.sort_by(|a, b| {
+            b.2.partial_cmp(&a.2)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.0.cmp(&b.0))
+        });
+        matches.truncate(limit);
+        matches
+    }
+
+    /// Direct connections departing on `date` as `(from_id, to_id,
+    /// flight_count)`, sorted by airport ids: the time-expanded graph
+    /// collapsed to one edge per airport pair.
+    pub fn adjacency(&self, date: NaiveDate) -> Vec<(usize, usize, usize)> {
+        let start = date.and_hms_opt(0, 0, 0).unwrap();
+        let end = start + Duration::days(1);
+        let mut counts: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+        for entry in self.airports.iter() {
+            let airport = entry.read().unwrap();
+            for (_, heap) in airport.outgoing.range(start..end) {
+                for flight in heap.iter() {
+                    let to = flight.flight().to.read().unwrap().id;
+                    *counts.entry((airport.id, to)).or_default() += 1;
+                }
+            }
+        }
+        counts
+            .into_iter()
+            .map(|((from, to), count)| (from, to, count))
+            .collect()
+    }
+
+    /// Degree centrality of every airport: the number of distinct airports
+    /// it has a direct flight to or from, over all dates, divided by the
+    /// number of other airports. A score of 1.0 means the airport connects
+    /// to every other one.
+    ///
+    /// Computing it walks every flight once, O(flights). The result is
+    /// cached until the next flight or airport is added or removed, through
+    /// this container or any clone of it.
+    pub fn hub_scores(&self) -> HashMap<usize, f64> {
+        let version = self.data_version();
+        if let Some((cached_at, scores)) = self.hub_scores.read().unwrap().as_ref() {
+            if *cached_at == version {
+                return scores.clone();
+            }
+        }
+        let mut neighbours: HashMap<usize, HashSet<usize>> = self
+            .airports
+            .iter()
+            .map(|entry| (*entry.key(), HashSet::new()))
+            .collect();
+        for entry in self.airports.iter() {
+            let airport = entry.read().unwrap();
+            for heap in airport.outgoing.values() {
+                for flight in heap.iter() {
+                    let to = flight.flight().to.read().unwrap().id;
+                    neighbours.entry(airport.id).or_default().insert(to);
+                    neighbours.entry(to).or_default().insert(airport.id);
+                }
+            }
+        }
+        let others = neighbours.len().saturating_sub(1).max(1) as f64;
+        let scores: HashMap<usize, f64> = neighbours
+            .into_iter()
+            .map(|(id, connected)| (id, connected.len() as f64 / others))
+            .collect();
+        *self.hub_scores.write().unwrap() = Some((version, scores.clone()));
+        scores
+    }
+
+    /// Counts of what is loaded, e.g. to check an import was not empty.
+    pub fn stats(&self) -> ContainerStats {
+        let mut num_flights = 0;
+        let mut earliest_departure: Option<NaiveDateTime> = None;
+        let mut latest_departure: Option<NaiveDateTime> = None;
+        let mut out_degrees = 0;
+        for entry in self.airports.iter() {
+            let airport = entry.read().unwrap();
+            if let Some((first, _)) = airport.outgoing.first_key_value() {
+                earliest_departure = Some(earliest_departure.map_or(*first, |x| x.min(*first)));
+            }
+            if let Some((last, _)) = airport.outgoing.last_key_value() {
+                latest_departure = Some(latest_departure.map_or(*last, |x| x.max(*last)));
+            }
+            let mut destinations = HashSet::new();
+            for heap in airport.outgoing.values() {
+                num_flights += heap.len();
+                for flight in heap.iter() {
+                    destinations.insert(flight.flight().to.read().unwrap().id);
+                }
+            }
+            out_degrees += destinations.len();
+        }
+        let num_airports = self.airports.len();
+        ContainerStats {
+            num_airports,
+            num_flights,
+            earliest_departure,
+            latest_departure,
+            avg_out_degree: out_degrees as f64 / num_airports.max(1) as f64,
+        }
+    }
+
+    /// Checks the graph's indexes and schedule and returns everything that
+    /// looks wrong. Each warning is also logged.
+    pub fn validate(&self) -> Vec<ValidationWarning> {
+        let mut warnings = vec![];
+        for entry in self.airports.iter() {
+            let airport = entry.read().unwrap();
+            for (key, heap) in airport.outgoing.iter() {
+                for flight in heap.iter().map(|x| x.flight()) {
+                    if flight.depart_at != *key {
+                        warnings.push(ValidationWarning::BucketKeyMismatch {
+                            airport_id: airport.id,
+                            flight_id: flight.flight_id,
+                            key: *key,
+                            depart_at: flight.depart_at,
+                        });
+                    }
+                }
+            }
+        }
+        warnings.extend(self.unconnectable_airports());
+        for warning in &warnings {
+            tracing::warn!(?warning, "graph validation");
+        }
+        warnings
+    }
+
+    /// Verifies that every flight in `flights_container` is indexed exactly
+    /// once, under its origin airport and departure time, and that every
+    /// indexed flight is in the container. Problems are sorted by flight id.
+    pub fn check_index_consistency(&self) -> Result<(), Vec<Inconsistency>> {
+        let mut indexed: BTreeMap<usize, Vec<(usize, NaiveDateTime)>> = BTreeMap::new();
+        for entry in self.airports.iter() {
+            let airport = entry.read().unwrap();
+            for (key, heap) in airport.outgoing.iter() {
+                for flight in heap.iter() {
+                    indexed
+                        .entry(flight.flight().flight_id)
+                        .or_default()
+                        .push((airport.id, *key));
+                }
+            }
+        }
+        let flights = self.flights_container.read().unwrap();
+        let mut problems = vec![];
+        for (&flight_id, places) in &indexed {
+            if flights.get_flight(flight_id).is_none() {
+                for &(airport_id, _) in places {
+                    problems.push(Inconsistency::Orphaned {
+                        flight_id,
+                        airport_id,
+                    });
+                }
+            }
+        }
+        for flight in flights.flights.iter() {
+            let flight_id = flight.flight_id;
+            let places = indexed.get(&flight_id).map(|x| x.as_slice()).unwrap_or(&[]);
+            match places {
+                [] => problems.push(Inconsistency::NotIndexed { flight_id }),
+                [(airport_id, key)] => {
+                    if *airport_id != flight.from.read().unwrap().id || *key != flight.depart_at {
+                        problems.push(Inconsistency::Misplaced {
+                            flight_id,
+                            airport_id: *airport_id,
+                            key: *key,
+                        });
+                    }
+                }
+                _ => problems.push(Inconsistency::IndexedRepeatedly {
+                    flight_id,
+                    occurrences: places.len(),
+                }),
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        problems.sort_by_key(|x| match x {
+            Inconsistency::NotIndexed { flight_id }
+            | Inconsistency::IndexedRepeatedly { flight_id, .. }
+            | Inconsistency::Misplaced { flight_id, .. }
+            | Inconsistency::Orphaned { flight_id, .. } => *flight_id,
+        });
+        Err(problems)
+    }
+
+    // Airports with both inbound and outbound flights where no inbound
+    // flight arrives early enough to catch any outbound one.
+    fn unconnectable_airports(&self) -> Vec<ValidationWarning> {
+        let mut first_arrivals: BTreeMap<usize, NaiveDateTime> = BTreeMap::new();
+        for flight in self.flights_container.read().unwrap().flights.iter() {
+            let to = flight.to.read().unwrap().id;
+            let first = first_arrivals.entry(to).or_insert(flight.arrive_at);
+            *first = (*first).min(flight.arrive_at);
+        }
+        let mut warnings = vec![];
+        for (airport_id, first_arrival) in first_arrivals {
+            let Some(airport) = self.airports.get(&airport_id) else {
+                continue;
+            };
+            let Some((&last_departure, _)) = airport.read().unwrap().outgoing.last_key_value()
+            else {
+                continue;
+            };
+            if last_departure < first_arrival + Duration::minutes(MIN_CONNECTION_MINUTES) {
+                warnings.push(ValidationWarning::NoPossibleConnection {
+                    airport_id,
+                    first_arrival,
+                    last_departure,
+                });
+            }
+        }
+        warnings
+    }
+
     pub fn load_airports_from_csv(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
+        self.load_airports_from_csv_with_columns(file_path, &AirportColumnMap::default())
+    }
+
+    pub fn load_airports_from_csv_with_columns(
+        &self,
+        file_path: &str,
+        columns: &AirportColumnMap,
+    ) -> Result<(), Box<dyn Error>> {
         let file = File::open(file_path)?;
-        let mut rdr = csv::Reader::from_reader(file);
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
 
         for result in rdr.records() {
             let record = result?;
 
-            if record.len() >= 2 {
-                let id = record[0].parse::<usize>()?;
-                let name = record[3].to_string();
-
-                let airport = Airport {
-                    id,
-                    name,
-                    outgoing: BTreeMap::new(),
+            let (Some(id), Some(name)) = (record.get(columns.id), record.get(columns.name)) else {
+                continue;
+            };
+            let id = id.parse::<usize>()?;
+            let timezone = match columns.timezone {
+                Some(index) => {
+                    let zone = record.get(index).unwrap_or_default().trim();
+                    zone.parse::<Tz>().unwrap_or_else(|_| {
+                        tracing::warn!(airport_id = id, zone, "invalid timezone, using UTC");
+                        Tz::UTC
+                    })
+                }
+                None => Tz::UTC,
+            };
+            let coordinate = |column: Option<usize>, field: &str| -> f64 {
+                let Some(index) = column else {
+                    return 0.0;
                 };
+                let value = record.get(index).unwrap_or_default().trim();
+                value.parse::<f64>().unwrap_or_else(|_| {
+                    tracing::warn!(airport_id = id, field, value, "invalid coordinate, using 0");
+                    0.0
+                })
+            };
 
-                self.add_airport(airport);
-            }
+            let airport = Airport {
+                id,
+                name: name.to_string(),
+                latitude: coordinate(columns.latitude, "latitude"),
+                longitude: coordinate(columns.longitude, "longitude"),
+                timezone,
+                outgoing: BTreeMap::new(),
+            };
+
+            self.add_airport(airport);
         }
 
         Ok(())
@@ -122,11 +838,127 @@ impl AirportsContainer {
 pub struct Airport {
     pub id: usize,
     pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Zone the airport's schedule times are local to.
+    pub timezone: Tz,
     pub outgoing: BTreeMap<NaiveDateTime, BinaryHeap<FlightEdgeWrapper>>,
 }
 
+/// Serializable view of an [`Airport`] without its outgoing flights, which
+/// are rebuilt from the flight data instead.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AirportDTO {
+    pub id: usize,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub timezone: Tz,
+}
+
+/// Immutable copy of the graph taken by `AirportsContainer::read_view`:
+/// each airport's departures sorted by UTC departure time, with the ids of
+/// both ends resolved, so reading it needs no locks.
+#[derive(Clone, Debug, Default)]
+pub struct SearchView {
+    departures: HashMap<usize, Vec<ViewFlight>>,
+    version: u64,
+}
+
+/// A flight in a `SearchView`.
+#[derive(Clone, Debug)]
+pub struct ViewFlight {
+    pub flight: Arc<FlightEdge>,
+    pub from: usize,
+    pub to: usize,
+}
+
+impl SearchView {
+    /// `AirportsContainer::data_version` when the view was taken.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Flights leaving `airport` from `start` to `end` inclusive, both UTC,
+    /// earliest first.
+    pub fn departures(
+        &self,
+        airport: usize,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> &[ViewFlight] {
+        let Some(flights) = self.departures.get(&airport) else {
+            return &[];
+        };
+        let first = flights.partition_point(|x| x.flight.depart_utc < start);
+        let last = flights.partition_point(|x| x.flight.depart_utc <= end);
+        &flights[first..last.max(first)]
+    }
+}
+
+// File contents written by `AirportsContainer::save_snapshot`.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    airports: Vec<AirportDTO>,
+    flights: Vec<FlightDTO>,
+}
+
+impl AirportDTO {
+    /// The airport in the form `AirportsContainer::add_airport` accepts.
+    pub fn to_airport(&self) -> Airport {
+        Airport {
+            id: self.id,
+            name: self.name.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            timezone: self.timezone,
+            outgoing: BTreeMap::new(),
+        }
+    }
+}
+
 impl Airport {
+    pub fn to_dto(&self) -> AirportDTO {
+        AirportDTO {
+            id: self.id,
+            name: self.name.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            timezone: self.timezone,
+        }
+    }
+
+    /// Converts a local time at this airport to UTC. A time skipped by a
+    /// daylight-saving change is read with the offset in force around it.
+    pub fn to_utc(&self, local: NaiveDateTime) -> NaiveDateTime {
+        match self.timezone.from_local_datetime(&local).earliest() {
+            Some(time) => time.naive_utc(),
+            None => {
+                let offset = self.timezone.offset_from_utc_datetime(&local).fix();
+                local - Duration::seconds(offset.local_minus_utc() as i64)
+            }
+        }
+    }
+
+    /// A local time at this airport with its zone abbreviation, e.g.
+    /// "2024-01-14 06:00:00 EST".
+    pub fn format_local(&self, local: NaiveDateTime) -> String {
+        match self.timezone.from_local_datetime(&local).earliest() {
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S %Z").to_string(),
+            None => local.to_string(),
+        }
+    }
+
+    /// Converts a UTC time to local time at this airport.
+    pub fn to_local(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        self.timezone.from_utc_datetime(&utc).naive_local()
+    }
+
     fn add_flight(&mut self, flight: Arc<FlightEdge>, departure_date: NaiveDateTime) {
+        debug_assert_eq!(
+            departure_date, flight.depart_at,
+            "outgoing bucket key must match the flight's departure"
+        );
         // Create a new BinaryHeap if it does not exist.
         let found_departure_slot = self.outgoing.contains_key(&departure_date);
         if !found_departure_slot {
@@ -141,29 +973,479 @@ impl Airport {
     fn remove_flight(&mut self, flight_id: usize, departure_date: NaiveDateTime) {
         if let Some(heap) = self.outgoing.get_mut(&departure_date) {
             heap.retain(|x| x.flight().clone().flight_id != flight_id);
-            if heap.len() == 0 {
+            if heap.is_empty() {
                 self.outgoing.remove(&departure_date);
             }
         }
     }
 
+    // Whether the flight is in this airport's departure index.
+    fn departs(&self, flight_id: usize, departure_date: NaiveDateTime) -> bool {
+        self.outgoing
+            .get(&departure_date)
+            .is_some_and(|heap| heap.iter().any(|x| x.flight().flight_id == flight_id))
+    }
+
+    /// Flights departing in `[start, end]`, both inclusive; `end` defaults
+    /// to a day after `start`. Each flight's own departure is checked, not
+    /// just the key of the slot it is stored under. Slots come in time
+    /// order, and the flights of a slot cheapest first, equal costs by id.
     pub fn flights_between(
         &self,
         start: NaiveDateTime,
         end: Option<NaiveDateTime>,
     ) -> Vec<Arc<FlightEdge>> {
-        let mut flights = vec![];
         let end_date = end.unwrap_or(start + Duration::hours(24));
+        if end_date < start {
+            return vec![];
+        }
+        self.outgoing
+            .range(start..=end_date)
+            .flat_map(|(_, heap)| {
+                // The heap's own layout is not its order; popping order is.
+                let mut slot: Vec<&FlightEdgeWrapper> = heap.iter().collect();
+                slot.sort_by(|a, b| b.cmp(a));
+                slot.into_iter().map(|x| x.flight())
+            })
+            .filter(|flight| start <= flight.depart_at && flight.depart_at <= end_date)
+            .collect()
+    }
+}
 
-        // Continue with collecting flights in range
-        for (date, heap) in self.outgoing.range(start..=end_date) {
-            if date > &end_date {
-                break;
-            }
-            for flight in heap.iter() {
-                flights.push(flight.flight().clone());
-            }
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::search::{Search, SearchQuery};
+    use crate::testutil::{airport, flight, graph, ids, query, temp_file, DATE};
+
+    fn two_flights() -> AirportsContainer {
+        graph(
+            2,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 0, 1, 200, "10:00", "11:00"),
+            ],
+        )
+    }
+
+    #[test]
+    fn removed_flights_stay_out_of_snapshots() {
+        let airports = two_flights();
+        airports.remove_flight(1);
+        let path = temp_file("removed.snapshot", "");
+        airports.save_snapshot(path.to_str().unwrap()).unwrap();
+
+        let reloaded = AirportsContainer::new();
+        reloaded.load_snapshot(path.to_str().unwrap()).unwrap();
+
+        let found = Search::new(reloaded).find(query(0, 1, 1, 5)).unwrap();
+        assert_eq!(ids(&found), vec![vec![0]]);
+    }
+
+    #[test]
+    fn updates_leave_removed_flights_removed() {
+        let airports = two_flights();
+        airports.remove_flight(1);
+
+        assert!(!airports.update_flight_cost(1, 50));
+        assert_eq!(airports.decrement_seats(1, 1), None);
+        assert_eq!(airports.check_index_consistency(), Ok(()));
+        let found = Search::new(airports).find(query(0, 1, 1, 5)).unwrap();
+        assert_eq!(ids(&found), vec![vec![0]]);
+    }
+
+    fn named(names: &[&str]) -> AirportsContainer {
+        let airports = AirportsContainer::new();
+        for (id, name) in names.iter().enumerate() {
+            airports.add_airport(Airport {
+                name: name.to_string(),
+                ..airport(id)
+            });
+        }
+        airports
+    }
+
+    #[test]
+    fn resolve_name_returns_every_match() {
+        let airports = named(&["London Heathrow", "Paris Orly", "London Gatwick"]);
+        assert_eq!(airports.resolve_name("London"), vec![0, 2]);
+        assert_eq!(airports.resolve_name("orly"), vec![1]);
+        assert_eq!(airports.resolve_name("Berlin"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn midnight_departures_are_bucketed_on_the_next_day() {
+        let airports = graph(2, vec![flight(0, 0, 1, 100, "24:00", "23:00")]);
+        let next_day =
+            NaiveDateTime::parse_from_str("2024-01-15 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let origin = airports.airports.get(&0).unwrap().read().unwrap().clone();
+        let keys: Vec<_> = origin.outgoing.keys().copied().collect();
+        assert_eq!(keys, vec![next_day]);
+        let flights = origin.flights_between(next_day, None);
+        assert_eq!(flights[0].depart_at, next_day);
+        assert_eq!(airports.validate(), vec![]);
+    }
+
+    #[test]
+    fn mutation_hook_sees_every_kind_of_change() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut airports = AirportsContainer::new();
+        let log = events.clone();
+        airports.set_mutation_hook(Arc::new(move |event: &MutationEvent| {
+            log.lock()
+                .unwrap()
+                .push((event.kind, event.airport_id, event.flight_id));
+        }));
+
+        airports.add_airport(airport(0));
+        airports.add_airport(airport(1));
+        airports.add_flight(flight(0, 0, 1, 100, "08:00", "09:00"));
+        airports.update_flight_cost(0, 50);
+        airports.remove_flight(0);
+        airports.remove_airport(1);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (MutationKind::AddAirport, 0, None),
+                (MutationKind::AddAirport, 1, None),
+                (MutationKind::AddFlight, 0, Some(0)),
+                (MutationKind::UpdateFlight, 0, Some(0)),
+                (MutationKind::RemoveFlight, 0, Some(0)),
+                (MutationKind::RemoveAirport, 1, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacency_counts_direct_flights_on_the_date() {
+        let airports = graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 0, 1, 100, "12:00", "13:00"),
+                flight(2, 1, 2, 100, "10:00", "11:00"),
+                flight(3, 2, 0, 100, "23:30", "23:59"),
+                FlightDTO {
+                    departure_date: "2024-01-15 08:00:00".to_string(),
+                    arrival_date: "2024-01-15 09:00:00".to_string(),
+                    ..flight(4, 0, 2, 100, "08:00", "09:00")
+                },
+            ],
+        );
+        let date = NaiveDate::from_ymd_opt(2024, 1, 14).unwrap();
+        assert_eq!(
+            airports.adjacency(date),
+            vec![(0, 1, 2), (1, 2, 1), (2, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn hub_scores_rank_the_centre_of_a_star_highest() {
+        // 0 flies to and from every other airport; the spokes only to 0.
+        let flights = (1..5)
+            .flat_map(|spoke| {
+                [
+                    flight(spoke * 2, 0, spoke, 100, "08:00", "09:00"),
+                    flight(spoke * 2 + 1, spoke, 0, 100, "10:00", "11:00"),
+                ]
+            })
+            .collect();
+        let scores = graph(5, flights).hub_scores();
+        assert_eq!(scores[&0], 1.0);
+        for spoke in 1..5 {
+            assert_eq!(scores[&spoke], 0.25);
+        }
+    }
+
+    fn at(time: &str) -> NaiveDateTime {
+        let time = format!("{} {}:00", DATE, time);
+        NaiveDateTime::parse_from_str(&time, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn validate_flags_airports_nothing_can_connect_through() {
+        // The only way out of 1 leaves before the only way in lands; 3
+        // connects fine.
+        let airports = graph(
+            4,
+            vec![
+                flight(0, 0, 1, 100, "12:00", "15:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+                flight(2, 0, 3, 100, "07:00", "08:00"),
+                flight(3, 3, 2, 100, "12:00", "13:00"),
+            ],
+        );
+        assert_eq!(
+            airports.validate(),
+            vec![ValidationWarning::NoPossibleConnection {
+                airport_id: 1,
+                first_arrival: at("15:00"),
+                last_departure: at("10:00"),
+            }]
+        );
+    }
+
+    #[test]
+    fn airport_schedules_can_be_cleared_and_restored() {
+        let airports = graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+                flight(2, 1, 2, 200, "12:00", "13:00"),
+            ],
+        );
+        let search = Search::new(airports);
+        let through_1 = || search.find_or_empty(query(0, 2, 3, 5));
+        assert_eq!(ids(&through_1()), vec![vec![0, 1], vec![0, 2]]);
+
+        let container = search.airports.read().unwrap();
+        let exported = serde_json::to_string(&container.export_airport_schedule(1)).unwrap();
+        assert_eq!(container.import_airport_schedule(1, vec![]), 0);
+        assert_eq!(container.export_airport_schedule(1), vec![]);
+        drop(container);
+        assert!(through_1().is_empty());
+
+        let schedule: Vec<FlightDTO> = serde_json::from_str(&exported).unwrap();
+        let container = search.airports.read().unwrap();
+        assert_eq!(container.import_airport_schedule(1, schedule), 2);
+        drop(container);
+        assert_eq!(ids(&through_1()), vec![vec![0, 1], vec![0, 2]]);
+    }
+
+    #[test]
+    fn index_checks_catch_either_index_drifting() {
+        let airports = two_flights();
+        assert_eq!(airports.check_index_consistency(), Ok(()));
+
+        // Flight 0 dropped from the container only, flight 1 from its
+        // airport only.
+        airports
+            .flights_container
+            .write()
+            .unwrap()
+            .remove_flight(0)
+            .unwrap();
+        let origin = airports.airports.get(&0).unwrap().clone();
+        origin.write().unwrap().remove_flight(1, at("10:00"));
+
+        assert_eq!(
+            airports.check_index_consistency(),
+            Err(vec![
+                Inconsistency::Orphaned {
+                    flight_id: 0,
+                    airport_id: 0,
+                },
+                Inconsistency::NotIndexed { flight_id: 1 },
+            ])
+        );
+    }
+
+    #[test]
+    fn airport_csvs_carry_timezones_into_connection_times() {
+        let path = temp_file(
+            "zones.csv",
+            "id,code,zone\n1,JFK,America/New_York\n2,LHR,Europe/London\n3,XXX,Not/AZone\n",
+        );
+        let airports = AirportsContainer::new();
+        let columns = AirportColumnMap {
+            id: 0,
+            name: 1,
+            timezone: Some(2),
+            latitude: None,
+            longitude: None,
+        };
+        airports
+            .load_airports_from_csv_with_columns(path.to_str().unwrap(), &columns)
+            .unwrap();
+        let zone = |id: usize| airports.airports.get(&id).unwrap().read().unwrap().timezone;
+        assert_eq!(zone(1), chrono_tz::America::New_York);
+        assert_eq!(zone(2), chrono_tz::Europe::London);
+        assert_eq!(zone(3), Tz::UTC);
+
+        // 18:00 in New York is 23:00 UTC, seven hours before 06:00 in London.
+        airports.add_flight(FlightDTO {
+            departure_date: format!("{} 18:00:00", DATE),
+            arrival_date: "2024-01-15 06:00:00".to_string(),
+            ..flight(0, 1, 2, 100, "00:00", "00:00")
+        });
+        let flights = airports.flights_container.read().unwrap();
+        let edge = flights.get_flight(0).unwrap();
+        assert_eq!(edge.depart_utc, at("23:00"));
+        assert_eq!(edge.arrive_utc - edge.depart_utc, Duration::hours(7));
+    }
+
+    #[test]
+    fn airport_codes_resolve_to_ids_for_searching() {
+        let airports = named(&["JFK", "LAX"]);
+        airports.add_flight(flight(0, 0, 1, 100, "08:00", "09:00"));
+        assert_eq!(airports.get_airport_by_code("lax"), Some(1));
+        assert_eq!(airports.get_airport_by_code("SFO"), None);
+
+        let by_code = SearchQuery {
+            from_code: Some("JFK".to_string()),
+            to_code: Some("LAX".to_string()),
+            ..query(5, 5, 1, 5)
+        };
+        let found = Search::new(airports).find(by_code).unwrap();
+        assert_eq!(ids(&found), vec![vec![0]]);
+    }
+
+    #[test]
+    fn cost_updates_reorder_a_departure_slot() {
+        let airports = graph(
+            2,
+            (0..5)
+                .map(|id| flight(id, 0, 1, 100 * (id as i64 + 1), "08:00", "09:00"))
+                .collect(),
+        );
+        let slot = |airports: &AirportsContainer| -> Vec<usize> {
+            let origin = airports.airports.get(&0).unwrap();
+            let flights = origin.read().unwrap().flights_between(at("08:00"), None);
+            flights.iter().map(|x| x.flight_id).collect()
+        };
+        assert_eq!(slot(&airports), vec![0, 1, 2, 3, 4]);
+
+        assert!(airports.update_flight_cost(0, 450));
+        assert_eq!(slot(&airports), vec![1, 2, 3, 0, 4]);
+        assert!(airports.update_flight_cost(4, 50));
+        assert_eq!(slot(&airports), vec![4, 1, 2, 3, 0]);
+        assert!(airports.update_flight_cost(2, 200));
+        assert_eq!(slot(&airports), vec![4, 1, 2, 3, 0]);
+        assert_eq!(airports.check_index_consistency(), Ok(()));
+    }
+
+    #[test]
+    fn flights_between_checks_departures_not_slot_keys() {
+        let airports = graph(
+            2,
+            vec![
+                flight(0, 0, 1, 100, "07:59", "09:00"),
+                flight(1, 0, 1, 100, "08:30", "09:30"),
+                flight(2, 0, 1, 100, "09:01", "10:00"),
+            ],
+        );
+        let origin = airports.airports.get(&0).unwrap();
+        let mut origin = origin.write().unwrap();
+        // Store all three under one coarser slot that lies inside the window.
+        let slot: BinaryHeap<_> = std::mem::take(&mut origin.outgoing)
+            .into_values()
+            .flatten()
+            .collect();
+        origin.outgoing.insert(at("08:00"), slot);
+
+        let departing = |start, end| -> Vec<usize> {
+            let flights = origin.flights_between(at(start), Some(at(end)));
+            let mut ids: Vec<_> = flights.iter().map(|x| x.flight_id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(departing("08:00", "09:00"), vec![1]);
+        assert_eq!(departing("07:59", "09:01"), vec![0, 1, 2]);
+        assert_eq!(departing("08:00", "08:29"), Vec::<usize>::new());
+        assert_eq!(departing("09:00", "08:00"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn flights_in_the_same_minute_share_a_slot_in_a_stable_order() {
+        let costs = [200, 100, 200, 50, 100];
+        let airports = graph(
+            2,
+            costs
+                .iter()
+                .enumerate()
+                .map(|(id, &cost)| flight(id, 0, 1, cost, "08:00", "09:00"))
+                .collect(),
+        );
+        let slot = |airports: &AirportsContainer| -> Vec<usize> {
+            let origin = airports.airports.get(&0).unwrap();
+            let origin = origin.read().unwrap();
+            assert_eq!(origin.outgoing.len(), 1);
+            // Popping order: cheapest first, equal costs by id.
+            let heap = origin.outgoing[&at("08:00")].clone();
+            let sorted = heap.into_sorted_vec();
+            sorted.iter().rev().map(|x| x.flight().flight_id).collect()
+        };
+        assert_eq!(slot(&airports), vec![3, 1, 4, 0, 2]);
+
+        airports.remove_flight(4);
+        assert_eq!(slot(&airports), vec![3, 1, 0, 2]);
+        let origin = airports.airports.get(&0).unwrap();
+        let departing = origin.read().unwrap().flights_between(at("08:00"), None);
+        assert_eq!(departing.len(), costs.len() - 1);
+    }
+
+    #[test]
+    fn haversine_distance_matches_known_routes() {
+        let airports = AirportsContainer::new();
+        for (id, (latitude, longitude)) in [
+            (40.6413, -73.7781),  // JFK
+            (51.4700, -0.4543),   // LHR
+            (33.9416, -118.4085), // LAX
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            airports.add_airport(Airport {
+                latitude,
+                longitude,
+                ..airport(id)
+            });
         }
-        flights
+        let km = |a, b| airports.haversine_distance_km(a, b).unwrap();
+        assert!((km(0, 1) - 5555.0).abs() < 20.0, "{}", km(0, 1));
+        assert!((km(0, 2) - 3983.0).abs() < 20.0, "{}", km(0, 2));
+        assert_eq!(km(1, 0), km(0, 1));
+        assert_eq!(km(2, 2), 0.0);
+        assert_eq!(airports.haversine_distance_km(0, 3), None);
+    }
+
+    #[test]
+    fn fuzzy_search_tolerates_misspellings() {
+        let airports = named(&["LaGuardia", "JFK Kennedy", "Newark Liberty", "Kenai"]);
+        let found = airports.search_airports("kenedy", 2);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].0, found[0].1.as_str()), (1, "JFK Kennedy"));
+        assert!(found[0].2 > found[1].2, "{:?}", found);
+        assert_eq!(airports.search_airports("NEWARK", 1)[0].0, 2);
+        assert_eq!(airports.search_airports("  ", 5), vec![]);
+    }
+
+    #[test]
+    fn booking_the_last_seat_drops_a_flight() {
+        let airports = graph(
+            2,
+            vec![
+                FlightDTO {
+                    seats: 2,
+                    ..flight(0, 0, 1, 100, "08:00", "09:00")
+                },
+                flight(1, 0, 1, 200, "10:00", "11:00"),
+            ],
+        );
+        let search = Search::new(airports.clone());
+        let found = || ids(&search.find(query(0, 1, 1, 5)).unwrap());
+
+        assert_eq!(airports.decrement_seats(0, 1), Some(1));
+        assert_eq!(found(), vec![vec![0], vec![1]]);
+        assert_eq!(airports.decrement_seats(0, 5), Some(0));
+        assert_eq!(found(), vec![vec![1]]);
+        assert_eq!(airports.decrement_seats(1, 5), Some(UNLIMITED_SEATS));
+        assert_eq!(found(), vec![vec![1]]);
+        assert_eq!(airports.check_index_consistency(), Ok(()));
+    }
+
+    #[test]
+    fn hub_scores_follow_changes_made_through_another_handle() {
+        let airports = graph(3, vec![flight(0, 0, 1, 100, "08:00", "09:00")]);
+        let handle = airports.clone();
+        assert_eq!(handle.hub_scores()[&2], 0.0);
+
+        airports.add_flight(flight(1, 2, 0, 100, "10:00", "11:00"));
+        assert_eq!(handle.hub_scores()[&2], 0.5);
+        assert_eq!(handle.hub_scores(), airports.hub_scores());
     }
 }
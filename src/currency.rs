@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+/// Currency assumed for fares whose source does not state one.
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+/// An amount in whole units of an ISO 4217 currency.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Money {
+    pub amount: i64,
+    pub currency: String,
+}
+
+/// Converts amounts through a single base currency.
+pub trait CurrencyConverter: Send + Sync {
+    /// `amount` of `from` expressed in the base currency, or None if
+    /// `from` cannot be converted.
+    fn to_base(&self, amount: i64, from: &str) -> Option<i64>;
+    /// `amount` of the base currency expressed in `to`, or None if `to`
+    /// cannot be converted.
+    fn base_to(&self, amount: i64, to: &str) -> Option<i64>;
+}
+
+/// Fixed exchange rates, e.g. for tests or offline runs. Currencies
+/// without a rate cannot be converted.
+#[derive(Clone, Debug)]
+pub struct StaticRates {
+    // Units of base currency per unit of each currency.
+    rates: HashMap<String, f64>,
+}
+
+impl StaticRates {
+    pub fn new(base: &str) -> Self {
+        StaticRates {
+            rates: HashMap::from([(base.to_string(), 1.0)]),
+        }
+    }
+
+    /// Sets how many units of the base currency one unit of `currency` is
+    /// worth.
+    pub fn with_rate(mut self, currency: &str, rate: f64) -> Self {
+        self.rates.insert(currency.to_string(), rate);
+        self
+    }
+
+    fn rate(&self, currency: &str) -> Option<f64> {
+        self.rates.get(currency).copied()
+    }
+}
+
+impl CurrencyConverter for StaticRates {
+    fn to_base(&self, amount: i64, from: &str) -> Option<i64> {
+        Some((amount as f64 * self.rate(from)?).round() as i64)
+    }
+
+    fn base_to(&self, amount: i64, to: &str) -> Option<i64> {
+        Some((amount as f64 / self.rate(to)?).round() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_through_the_base_currency() {
+        let rates = StaticRates::new("USD").with_rate("EUR", 1.1);
+        assert_eq!(rates.to_base(100, "EUR"), Some(110));
+        assert_eq!(rates.to_base(100, "USD"), Some(100));
+        assert_eq!(rates.base_to(110, "EUR"), Some(100));
+    }
+
+    #[test]
+    fn currencies_without_a_rate_are_not_converted() {
+        let rates = StaticRates::new("USD").with_rate("EUR", 1.1);
+        assert_eq!(rates.to_base(100, "JPY"), None);
+        assert_eq!(rates.base_to(100, "JPY"), None);
+    }
+}
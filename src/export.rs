@@ -0,0 +1,325 @@
+use std::io::Write;
+
+use chrono::Utc;
+use serde_json::json;
+
+use crate::itinerary::Itinerary;
+use crate::search::PathState;
+
+const ICS_DATETIME: &str = "%Y%m%dT%H%M%S";
+
+/// Output formats shared by the CLI and any server front end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResultFormat {
+    Json,
+    /// One row per leg.
+    Csv,
+    /// Plain-text, column-aligned table.
+    Table,
+    /// A FeatureCollection with one LineString per itinerary.
+    GeoJson,
+    /// An iCalendar file with one event per leg.
+    Ics,
+}
+
+pub fn serialize_results(results: &[PathState], format: ResultFormat) -> String {
+    match format {
+        ResultFormat::Json => to_json(results),
+        ResultFormat::Csv => to_csv(results),
+        ResultFormat::Table => to_table(results),
+        ResultFormat::GeoJson => to_geojson(results),
+        ResultFormat::Ics => to_ics(results),
+    }
+}
+
+fn to_json(results: &[PathState]) -> String {
+    let itineraries: Vec<Itinerary> = results.iter().map(|x| x.to_itinerary()).collect();
+    serde_json::to_string_pretty(&itineraries).unwrap()
+}
+
+fn to_csv(results: &[PathState]) -> String {
+    let mut out = vec![];
+    export_results_csv(results, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+/// Writes `results` as CSV with one row per leg, each row also carrying
+/// its itinerary's total cost.
+pub fn export_results_csv(results: &[PathState], writer: impl Write) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "itinerary_index",
+        "leg_index",
+        "flight_id",
+        "from_id",
+        "to_id",
+        "depart_at",
+        "arrive_at",
+        "cost",
+        "total_cost",
+    ])?;
+    for (itinerary_index, itinerary) in results.iter().map(|x| x.to_itinerary()).enumerate() {
+        for (leg_index, leg) in itinerary.legs.iter().enumerate() {
+            writer.write_record([
+                itinerary_index.to_string(),
+                leg_index.to_string(),
+                leg.flight_id.to_string(),
+                leg.from_id.to_string(),
+                leg.to_id.to_string(),
+                leg.depart_at.to_string(),
+                leg.arrive_at.to_string(),
+                leg.cost.to_string(),
+                itinerary.total_cost.to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn to_table(results: &[PathState]) -> String {
+    let mut rows = vec![vec![
+        "#".to_string(),
+        "Flight".to_string(),
+        "From".to_string(),
+        "To".to_string(),
+        "Departs".to_string(),
+        "Arrives".to_string(),
+        "Cost".to_string(),
+    ]];
+    for (index, itinerary) in results.iter().map(|x| x.to_itinerary()).enumerate() {
+        for leg in &itinerary.legs {
+            rows.push(vec![
+                index.to_string(),
+                leg.flight_id.to_string(),
+                leg.from_name.clone(),
+                leg.to_name.clone(),
+                leg.depart_at.to_string(),
+                leg.arrive_at.to_string(),
+                leg.cost.to_string(),
+            ]);
+        }
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap())
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+fn to_geojson(results: &[PathState]) -> String {
+    let features: Vec<serde_json::Value> = results
+        .iter()
+        .map(|state| {
+            let legs = state.legs();
+            let mut coordinates = vec![];
+            if let Some(first) = legs.first() {
+                let from = first.from.read().unwrap();
+                coordinates.push([from.longitude, from.latitude]);
+            }
+            for leg in legs {
+                let to = leg.to.read().unwrap();
+                coordinates.push([to.longitude, to.latitude]);
+            }
+            let itinerary = state.to_itinerary();
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                },
+                "properties": {
+                    "flight_ids": itinerary.legs.iter().map(|x| x.flight_id).collect::<Vec<_>>(),
+                    "total_cost": itinerary.total_cost,
+                },
+            })
+        })
+        .collect();
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+    serde_json::to_string_pretty(&collection).unwrap()
+}
+
+// Times are written as floating local times since the schedule carries no
+// timezone information.
+fn to_ics(results: &[PathState]) -> String {
+    let stamp = Utc::now().format(ICS_DATETIME);
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//rust-flight-search//EN".to_string(),
+    ];
+    for (index, itinerary) in results.iter().map(|x| x.to_itinerary()).enumerate() {
+        for leg in &itinerary.legs {
+            lines.push("BEGIN:VEVENT".to_string());
+            lines.push(format!(
+                "UID:{}-{}@rust-flight-search",
+                index, leg.flight_id
+            ));
+            lines.push(format!("DTSTAMP:{}Z", stamp));
+            lines.push(format!("DTSTART:{}", leg.depart_at.format(ICS_DATETIME)));
+            lines.push(format!("DTEND:{}", leg.arrive_at.format(ICS_DATETIME)));
+            lines.push(format!(
+                "SUMMARY:Flight {} {} to {}",
+                leg.flight_id, leg.from_name, leg.to_name
+            ));
+            lines.push("END:VEVENT".to_string());
+        }
+    }
+    lines.push("END:VCALENDAR".to_string());
+    let mut out = lines.join("\r\n");
+    out.push_str("\r\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::search::Search;
+    use crate::testutil::{flight, graph, ids, query};
+
+    // A connection through 1 and a dearer nonstop.
+    fn results() -> Vec<PathState> {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+                flight(2, 0, 2, 300, "08:00", "10:00"),
+            ],
+        ));
+        search.find(query(0, 2, 3, 5)).unwrap()
+    }
+
+    // Groups per-leg `(itinerary, flight)` pairs back into itineraries.
+    fn regroup(legs: impl Iterator<Item = (usize, usize)>) -> Vec<Vec<usize>> {
+        let mut itineraries: Vec<Vec<usize>> = vec![];
+        for (itinerary, flight_id) in legs {
+            if itineraries.len() <= itinerary {
+                itineraries.push(vec![]);
+            }
+            itineraries[itinerary].push(flight_id);
+        }
+        itineraries
+    }
+
+    fn id_list(value: &Value) -> Vec<usize> {
+        value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x.as_u64().unwrap() as usize)
+            .collect()
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let results = results();
+        let json: Value =
+            serde_json::from_str(&serialize_results(&results, ResultFormat::Json)).unwrap();
+        let parsed: Vec<Vec<usize>> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|itinerary| {
+                let legs = itinerary["legs"].as_array().unwrap();
+                legs.iter()
+                    .map(|x| x["flight_id"].as_u64().unwrap() as usize)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(parsed, ids(&results));
+        assert_eq!(json[0]["total_cost"], 200);
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let results = results();
+        let csv = serialize_results(&results, ResultFormat::Csv);
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let rows: Vec<csv::StringRecord> = reader.records().map(|x| x.unwrap()).collect();
+        let legs = rows
+            .iter()
+            .map(|row| (row[0].parse().unwrap(), row[2].parse().unwrap()));
+        assert_eq!(regroup(legs), ids(&results));
+        assert_eq!(&rows[0][8], "200");
+    }
+
+    #[test]
+    fn table_round_trips() {
+        let results = results();
+        let table = serialize_results(&results, ResultFormat::Table);
+        let mut lines = table.lines();
+        assert!(lines.next().unwrap().starts_with("#  Flight  From"));
+        let legs = lines.map(|line| {
+            let cells: Vec<&str> = line.split_whitespace().collect();
+            (cells[0].parse().unwrap(), cells[1].parse().unwrap())
+        });
+        assert_eq!(regroup(legs), ids(&results));
+    }
+
+    #[test]
+    fn geojson_round_trips() {
+        let results = results();
+        let geojson: Value =
+            serde_json::from_str(&serialize_results(&results, ResultFormat::GeoJson)).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        let parsed: Vec<Vec<usize>> = features
+            .iter()
+            .map(|x| id_list(&x["properties"]["flight_ids"]))
+            .collect();
+        assert_eq!(parsed, ids(&results));
+        // A point per airport visited.
+        let points = features[0]["geometry"]["coordinates"].as_array().unwrap();
+        assert_eq!(points.len(), 3);
+    }
+
+    #[test]
+    fn ics_round_trips() {
+        let results = results();
+        let ics = serialize_results(&results, ResultFormat::Ics);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        let legs = ics.lines().filter_map(|line| {
+            let uid = line
+                .strip_prefix("UID:")?
+                .strip_suffix("@rust-flight-search")?;
+            let (itinerary, flight_id) = uid.split_once('-')?;
+            Some((itinerary.parse().unwrap(), flight_id.parse().unwrap()))
+        });
+        assert_eq!(regroup(legs), ids(&results));
+        assert!(ics.contains("DTSTART:20240114T080000\r\n"));
+    }
+
+    #[test]
+    fn csv_export_writes_a_header_and_a_row_per_leg() {
+        let mut buffer = vec![];
+        export_results_csv(&results(), &mut buffer).unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "itinerary_index,leg_index,flight_id,from_id,to_id,depart_at,arrive_at,cost,total_cost"
+        );
+        // Two legs for the connection, one for the nonstop.
+        assert_eq!(lines.len(), 1 + 3);
+        assert_eq!(
+            lines[3],
+            "1,0,2,0,2,2024-01-14 08:00:00,2024-01-14 10:00:00,300,300"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+use crate::search::PathState;
+
+/// How a constraint is enforced. Hard constraints drop any itinerary that
+/// violates them; soft ones keep it but add `penalty` to its ranking cost.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Strictness {
+    Hard,
+    Soft { penalty: i32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Constraint {
+    /// Every leg is flown by one of these airlines.
+    Airlines(Vec<String>),
+    /// No leg is flown by any of these airlines.
+    AvoidAirlines(Vec<String>),
+    /// No leg departs from or arrives at any of these airports.
+    AvoidAirports(Vec<usize>),
+    /// At most this many connections.
+    MaxStops(usize),
+    /// Price per passenger of at most this much, in the search's base
+    /// currency when it converts fares.
+    MaxPrice(i64),
+}
+
+impl Constraint {
+    // Every constraint is monotone: once a path violates it, every extension
+    // of that path does too, so hard constraints can prune during expansion.
+    fn violated_by(&self, state: &PathState) -> bool {
+        let path = state.legs();
+        match self {
+            Constraint::Airlines(airlines) => path.iter().any(|x| !airlines.contains(&x.airline)),
+            Constraint::AvoidAirlines(airlines) => {
+                path.iter().any(|x| airlines.contains(&x.airline))
+            }
+            Constraint::AvoidAirports(airports) => path.iter().any(|x| {
+                airports.contains(&x.from.read().unwrap().id)
+                    || airports.contains(&x.to.read().unwrap().id)
+            }),
+            Constraint::MaxStops(stops) => path.len() > stops + 1,
+            Constraint::MaxPrice(price) => state.cost() > *price,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub constraint: Constraint,
+    pub strictness: Strictness,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterSet {
+    pub filters: Vec<Filter>,
+}
+
+impl FilterSet {
+    pub fn hard(mut self, constraint: Constraint) -> Self {
+        self.filters.push(Filter {
+            constraint,
+            strictness: Strictness::Hard,
+        });
+        self
+    }
+
+    pub fn soft(mut self, constraint: Constraint, penalty: i32) -> Self {
+        self.filters.push(Filter {
+            constraint,
+            strictness: Strictness::Soft { penalty },
+        });
+        self
+    }
+
+    /// Most connections any hard `MaxStops` constraint allows, if there is
+    /// one.
+    pub fn max_stops(&self) -> Option<usize> {
+        self.filters
+            .iter()
+            .filter_map(|x| match (&x.constraint, x.strictness) {
+                (Constraint::MaxStops(stops), Strictness::Hard) => Some(*stops),
+                _ => None,
+            })
+            .min()
+    }
+
+    /// Whether `state` satisfies every hard constraint.
+    pub fn admits(&self, state: &PathState) -> bool {
+        self.filters
+            .iter()
+            .filter(|x| x.strictness == Strictness::Hard)
+            .all(|x| !x.constraint.violated_by(state))
+    }
+
+    /// Sum of the penalties of every soft constraint `state` violates. Each
+    /// constraint is charged at most once per itinerary.
+    pub fn penalty(&self, state: &PathState) -> i32 {
+        self.filters
+            .iter()
+            .filter_map(|x| match x.strictness {
+                Strictness::Soft { penalty } if x.constraint.violated_by(state) => Some(penalty),
+                _ => None,
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::currency::StaticRates;
+    use crate::flight::FlightDTO;
+    use crate::search::{Search, SearchQuery};
+    use crate::testutil::{flight, flown_by, graph, ids, query};
+
+    // A USD leg and a EUR leg, 100 each, worth 210 USD together.
+    fn mixed_currency_search() -> Search {
+        let airports = graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                FlightDTO {
+                    currency: "EUR".to_string(),
+                    ..flight(1, 1, 2, 100, "10:00", "11:00")
+                },
+            ],
+        );
+        Search::new(airports)
+            .with_currency_converter(Arc::new(StaticRates::new("USD").with_rate("EUR", 1.1)))
+    }
+
+    fn capped(price: i64) -> SearchQuery {
+        SearchQuery {
+            filters: FilterSet::default().hard(Constraint::MaxPrice(price)),
+            ..query(0, 2, 3, 5)
+        }
+    }
+
+    #[test]
+    fn max_price_compares_converted_totals() {
+        let search = mixed_currency_search();
+        assert_eq!(ids(&search.find_or_empty(capped(210))), vec![vec![0, 1]]);
+        assert!(search.find_or_empty(capped(205)).is_empty());
+    }
+
+    fn carrier_search() -> Search {
+        Search::new(graph(
+            2,
+            vec![
+                flown_by("AA", flight(0, 0, 1, 100, "08:00", "09:00")),
+                flown_by("BA", flight(1, 0, 1, 120, "08:00", "09:00")),
+            ],
+        ))
+    }
+
+    fn preferring_ba(filters: FilterSet) -> SearchQuery {
+        SearchQuery {
+            filters,
+            ..query(0, 1, 1, 5)
+        }
+    }
+
+    #[test]
+    fn soft_carrier_preference_reorders_without_excluding() {
+        let search = carrier_search();
+        let airlines = Constraint::Airlines(vec!["BA".to_string()]);
+        let soft = preferring_ba(FilterSet::default().soft(airlines.clone(), 50));
+        assert_eq!(ids(&search.find_or_empty(soft)), vec![vec![1], vec![0]]);
+        let hard = preferring_ba(FilterSet::default().hard(airlines));
+        assert_eq!(ids(&search.find_or_empty(hard)), vec![vec![1]]);
+    }
+}
@@ -1,20 +1,103 @@
 use chrono::{Duration, NaiveDateTime};
 use std::cmp::Ordering;
-use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::sync::{Arc, RwLock};
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
 
 use crate::airport::Airport;
+use crate::currency::DEFAULT_CURRENCY;
+
+/// Seat count for flights without inventory data; they never sell out.
+pub const UNLIMITED_SEATS: u32 = u32::MAX;
+
+/// Booking cabin. A physical flight sold in several cabins is stored as one
+/// edge per cabin.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub enum Cabin {
+    #[default]
+    Economy,
+    Premium,
+    Business,
+    First,
+}
 
 #[derive(Clone, Debug)]
 pub struct FlightEdge {
     pub flight_id: usize,
     pub to: Arc<RwLock<Airport>>,
     pub from: Arc<RwLock<Airport>>,
-    pub cost: i32,
+    pub cost: i64,
+    /// ISO 4217 code `cost` is in.
+    pub currency: String,
+    pub distance: i32,
+    pub cabin: Cabin,
+    /// Seats still available in `cabin`.
+    pub seats: u32,
+    pub airline: String,
+    /// Number within `airline`, e.g. "100" for AA100.
+    pub flight_number: String,
+    /// Other airlines selling this same physical flight.
+    pub codeshares: Vec<String>,
+    /// Local time at `to`.
     pub arrive_at: NaiveDateTime,
+    /// Local time at `from`.
     pub depart_at: NaiveDateTime,
+    pub arrive_utc: NaiveDateTime,
+    pub depart_utc: NaiveDateTime,
+}
+
+impl FlightEdge {
+    /// Airline code and flight number together, e.g. "AA100". Empty when
+    /// neither is known.
+    pub fn designator(&self) -> String {
+        format!("{}{}", self.airline, self.flight_number)
+    }
+
+    /// The flight in the form `AirportsContainer::add_flight` accepts.
+    pub fn to_dto(&self) -> FlightDTO {
+        FlightDTO {
+            flight_id: self.flight_id,
+            from: self.from.read().unwrap().id,
+            to: self.to.read().unwrap().id,
+            cost: self.cost,
+            currency: self.currency.clone(),
+            distance: self.distance,
+            cabin: self.cabin,
+            seats: self.seats,
+            airline: self.airline.clone(),
+            flight_number: self.flight_number.clone(),
+            codeshares: self.codeshares.clone(),
+            arrival_date: self.arrive_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            departure_date: self.depart_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            duration_minutes: Some((self.arrive_utc - self.depart_utc).num_minutes()),
+        }
+    }
+}
+
+/// Identifies one cabin of a physical flight, so a flight listed again can
+/// be recognised: route, local departure time, airline and cabin.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FlightKey {
+    pub from: usize,
+    pub to: usize,
+    pub depart_at: NaiveDateTime,
+    pub airline: String,
+    pub cabin: Cabin,
+}
+
+impl FlightEdge {
+    pub fn key(&self) -> FlightKey {
+        FlightKey {
+            from: self.from.read().unwrap().id,
+            to: self.to.read().unwrap().id,
+            depart_at: self.depart_at,
+            airline: self.airline.clone(),
+            cabin: self.cabin,
+        }
+    }
 }
 
 impl Hash for FlightEdge {
@@ -31,17 +114,49 @@ impl PartialEq for FlightEdge {
 }
 impl Eq for FlightEdge {}
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct FlightDTO {
     pub flight_id: usize,
     pub from: usize,
     pub to: usize,
-    pub cost: i32,
+    pub cost: i64,
+    /// `DEFAULT_CURRENCY` when missing, as in data written before fares
+    /// carried one.
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub distance: i32,
+    #[serde(default)]
+    pub cabin: Cabin,
+    /// `UNLIMITED_SEATS` when missing.
+    #[serde(default = "unlimited_seats")]
+    pub seats: u32,
+    #[serde(default)]
+    pub airline: String,
+    #[serde(default)]
+    pub flight_number: String,
+    #[serde(default)]
+    pub codeshares: Vec<String>,
     pub arrival_date: String,
     pub departure_date: String,
+    /// Scheduled block time. When given it decides the arrival, so flights
+    /// of any length land on the right day; `arrival_date` is then ignored.
+    #[serde(default)]
+    pub duration_minutes: Option<i64>,
 }
+
+fn default_currency() -> String {
+    DEFAULT_CURRENCY.to_string()
+}
+
+fn unlimited_seats() -> u32 {
+    UNLIMITED_SEATS
+}
+
 pub struct FlightsContainer {
     pub flights: DashMap<usize, Arc<FlightEdge>>,
+    // Id of the flight stored under each key.
+    by_key: DashMap<FlightKey, usize>,
 }
 
 impl FlightDTO {
@@ -60,6 +175,19 @@ impl FlightDTO {
         datetime_str.to_string()
     }
 
+    /// Checks both dates are "YYYY-MM-DD HH:MM:SS", as `to_edge` expects, so
+    /// importers can report a bad record instead of panicking on it.
+    pub fn check_dates(&self) -> Result<(), String> {
+        for (name, date) in [
+            ("departure_date", &self.departure_date),
+            ("arrival_date", &self.arrival_date),
+        ] {
+            NaiveDateTime::parse_from_str(&Self::fix_datetime_format(date), "%Y-%m-%d %H:%M:%S")
+                .map_err(|_| format!("malformed {} {:?}", name, date))?;
+        }
+        Ok(())
+    }
+
     pub fn to_edge(&self, from: Arc<RwLock<Airport>>, to: Arc<RwLock<Airport>>) -> FlightEdge {
         // println!("Creating flight edge from {} to {} at {}", from.read().unwrap().name, to.read().unwrap().name, &self.arrival_date);
 
@@ -67,22 +195,47 @@ impl FlightDTO {
         let fixed_arrival_date = Self::fix_datetime_format(&self.arrival_date);
         let fixed_departure_date = Self::fix_datetime_format(&self.departure_date);
 
-        let mut arrival_date =
-            NaiveDateTime::parse_from_str(&fixed_arrival_date, "%Y-%m-%d %H:%M:%S").unwrap();
         let departure_date =
             NaiveDateTime::parse_from_str(&fixed_departure_date, "%Y-%m-%d %H:%M:%S").unwrap();
-
-        if arrival_date < departure_date {
-            arrival_date = arrival_date + Duration::days(1);
-        }
+        let departure_utc = from.read().unwrap().to_utc(departure_date);
+        let (arrival_date, arrival_utc) = {
+            let to = to.read().unwrap();
+            match self.duration_minutes {
+                Some(minutes) => {
+                    let arrival_utc = departure_utc + Duration::minutes(minutes);
+                    (to.to_local(arrival_utc), arrival_utc)
+                }
+                None => {
+                    let mut arrival_date =
+                        NaiveDateTime::parse_from_str(&fixed_arrival_date, "%Y-%m-%d %H:%M:%S")
+                            .unwrap();
+                    // Without a duration, an arrival before the departure is
+                    // taken to be on the next day, as schedules that only
+                    // give times of day write overnight flights.
+                    if to.to_utc(arrival_date) < departure_utc {
+                        arrival_date += Duration::days(1);
+                    }
+                    (arrival_date, to.to_utc(arrival_date))
+                }
+            }
+        };
 
         FlightEdge {
             flight_id: self.flight_id,
             to,
             from,
             cost: self.cost,
+            currency: self.currency.clone(),
+            distance: self.distance,
+            cabin: self.cabin,
+            seats: self.seats,
+            airline: self.airline.clone(),
+            flight_number: self.flight_number.clone(),
+            codeshares: self.codeshares.clone(),
             arrive_at: arrival_date,
             depart_at: departure_date,
+            arrive_utc: arrival_utc,
+            depart_utc: departure_utc,
         }
     }
 
@@ -90,30 +243,59 @@ impl FlightDTO {
         let fixed_departure_date = Self::fix_datetime_format(&self.departure_date);
         NaiveDateTime::parse_from_str(&fixed_departure_date, "%Y-%m-%d %H:%M:%S").unwrap()
     }
+
+    pub fn key(&self) -> FlightKey {
+        FlightKey {
+            from: self.from,
+            to: self.to,
+            depart_at: self.departure_date(),
+            airline: self.airline.clone(),
+            cabin: self.cabin,
+        }
+    }
+}
+
+impl Default for FlightsContainer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FlightsContainer {
     pub fn new() -> Self {
         FlightsContainer {
             flights: DashMap::new(),
+            by_key: DashMap::new(),
         }
     }
     pub fn add_flight(&mut self, flight: FlightEdge) -> Arc<FlightEdge> {
         let flight = Arc::new(flight);
-        self.flights.insert(flight.flight_id, flight.clone());
+        if let Some(replaced) = self.flights.insert(flight.flight_id, flight.clone()) {
+            self.by_key
+                .remove_if(&replaced.key(), |_, id| *id == replaced.flight_id);
+        }
+        self.by_key.insert(flight.key(), flight.flight_id);
         flight
     }
 
+    /// Id of the flight stored under `key`, if any.
+    pub fn find_by_key(&self, key: &FlightKey) -> Option<usize> {
+        self.by_key.get(key).map(|x| *x)
+    }
+
+    /// Highest flight id stored, if any.
+    pub fn max_flight_id(&self) -> Option<usize> {
+        self.flights.iter().map(|x| *x.key()).max()
+    }
+
     pub fn get_flight(&self, flight_id: usize) -> Option<Arc<FlightEdge>> {
-        if let Some(flight) = self.flights.get(&flight_id) {
-            Some(flight.clone())
-        } else {
-            None
-        }
+        self.flights.get(&flight_id).map(|flight| flight.clone())
     }
 
     pub fn remove_flight(&mut self, flight_id: usize) -> Result<(), &str> {
-        if self.flights.remove(&flight_id).is_some() {
+        if let Some((_, flight)) = self.flights.remove(&flight_id) {
+            self.by_key
+                .remove_if(&flight.key(), |_, id| *id == flight_id);
             Ok(())
         } else {
             Err("Flight not found.")
@@ -136,8 +318,8 @@ impl FlightEdgeWrapper {
 
 impl PartialEq for FlightEdgeWrapper {
     fn eq(&self, other: &Self) -> bool {
-        // Compare based on flight cost
-        self.0.clone().cost == other.0.clone().cost
+        // Compare based on flight cost, then id, consistently with `cmp`
+        self.0.cost == other.0.cost && self.0.flight_id == other.0.flight_id
     }
 }
 
@@ -145,13 +327,113 @@ impl Eq for FlightEdgeWrapper {}
 
 impl PartialOrd for FlightEdgeWrapper {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // Reverse the order: lower cost is considered "greater" for BinaryHeap
-        other.0.clone().cost.partial_cmp(&self.0.clone().cost)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for FlightEdgeWrapper {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.0.clone().cost.cmp(&self.0.clone().cost)
+        // Reverse the order: lower cost is considered "greater" for BinaryHeap.
+        // Equal costs fall back to the lower id, so a slot's order does not
+        // depend on insertion order.
+        other
+            .0
+            .cost
+            .cmp(&self.0.cost)
+            .then(other.0.flight_id.cmp(&self.0.flight_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{airport, flight};
+
+    #[test]
+    fn flight_dtos_round_trip_through_json() {
+        let dto = FlightDTO {
+            airline: "AA".to_string(),
+            flight_number: "100".to_string(),
+            codeshares: vec!["BA".to_string()],
+            cabin: Cabin::Business,
+            seats: 4,
+            duration_minutes: Some(75),
+            ..flight(3, 1, 2, 250, "08:00", "09:15")
+        };
+        let json = serde_json::to_string(&dto).unwrap();
+        assert!(json.contains("\"departure_date\":\"2024-01-14 08:00:00\""));
+        assert_eq!(serde_json::from_str::<FlightDTO>(&json).unwrap(), dto);
+    }
+
+    #[test]
+    fn flight_dtos_read_lines_written_before_the_newer_fields() {
+        let line = r#"{"flight_id":3,"from":1,"to":2,"cost":250,"arrival_date":"2024-01-14 09:15:00","departure_date":"2024-01-14 08:00:00"}"#;
+        let dto: FlightDTO = serde_json::from_str(line).unwrap();
+        // In dollars, economy, never selling out, with no distance.
+        let expected = FlightDTO {
+            distance: 0,
+            ..flight(3, 1, 2, 250, "08:00", "09:15")
+        };
+        assert_eq!(dto, expected);
+        // Written back out, it reads the same again.
+        let json = serde_json::to_string(&dto).unwrap();
+        assert_eq!(serde_json::from_str::<FlightDTO>(&json).unwrap(), dto);
+    }
+
+    fn landing(dto: FlightDTO) -> (NaiveDateTime, NaiveDateTime) {
+        let at = |id| Arc::new(RwLock::new(airport(id)));
+        let edge = dto.to_edge(at(0), at(1));
+        (edge.depart_at, edge.arrive_at)
+    }
+
+    fn on(datetime: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn same_day_flights_land_on_their_arrival_date() {
+        assert_eq!(
+            landing(flight(0, 0, 1, 100, "08:00", "09:15")),
+            (on("2024-01-14 08:00:00"), on("2024-01-14 09:15:00"))
+        );
+    }
+
+    #[test]
+    fn red_eyes_given_as_times_of_day_land_the_next_day() {
+        assert_eq!(
+            landing(flight(0, 0, 1, 100, "22:00", "06:00")),
+            (on("2024-01-14 22:00:00"), on("2024-01-15 06:00:00"))
+        );
+    }
+
+    #[test]
+    fn arrivals_already_on_the_next_date_are_kept() {
+        let dto = FlightDTO {
+            arrival_date: "2024-01-15 06:00:00".to_string(),
+            ..flight(0, 0, 1, 100, "22:00", "00:00")
+        };
+        assert_eq!(landing(dto).1, on("2024-01-15 06:00:00"));
+    }
+
+    #[test]
+    fn durations_decide_arrivals_over_a_day_later() {
+        let dto = FlightDTO {
+            duration_minutes: Some(26 * 60),
+            ..flight(0, 0, 1, 100, "22:00", "06:00")
+        };
+        assert_eq!(landing(dto).1, on("2024-01-16 00:00:00"));
+    }
+
+    #[test]
+    fn midnight_written_as_24_00_is_the_next_day_at_both_ends() {
+        let dto = FlightDTO {
+            departure_date: "2024-01-14 24:00:00".to_string(),
+            arrival_date: "2024-01-15 24:00:00".to_string(),
+            ..flight(0, 0, 1, 100, "00:00", "00:00")
+        };
+        assert_eq!(
+            landing(dto),
+            (on("2024-01-15 00:00:00"), on("2024-01-16 00:00:00"))
+        );
     }
 }
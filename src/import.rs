@@ -1,153 +1,1149 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use csv::StringRecord;
 
 use crate::airport::{Airport, AirportsContainer};
-use crate::flight::FlightDTO;
+use crate::currency::DEFAULT_CURRENCY;
+use crate::flight::{Cabin, FlightDTO, UNLIMITED_SEATS};
 
 pub trait FlightImporter {
-    fn import_flights(&self, file_path: &str) -> Result<usize, Box<dyn Error>>;
+    /// Imports the file and accounts for every row it skipped.
+    fn import_with_report(&self, file_path: &str) -> Result<ImportReport, Box<dyn Error>>;
+
+    /// Imports the file and returns how many flights were added.
+    fn import_flights(&self, file_path: &str) -> Result<usize, Box<dyn Error>> {
+        Ok(self.import_with_report(file_path)?.imported)
+    }
+}
+
+/// A row the importer could not turn into a flight.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportError {
+    /// One-based line in the source file, when known.
+    pub line: Option<u64>,
+    pub reason: String,
+}
+
+/// Outcome of an import. Duplicates merged by a `DedupPolicy` count as
+/// neither imported nor skipped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+    pub errors: Vec<ImportError>,
+    /// Rows left out by `CsvFlightImporter::import_flights_append` because
+    /// the flight was already loaded.
+    pub already_loaded: usize,
+}
+
+impl ImportReport {
+    fn skip(&mut self, line: Option<u64>, reason: String) {
+        tracing::debug!(line, reason = %reason, "skipping row");
+        self.skipped += 1;
+        self.errors.push(ImportError { line, reason });
+    }
+
+    /// Share of rows that were skipped, from 0 to 1.
+    pub fn skipped_fraction(&self) -> f64 {
+        let total = self.imported + self.skipped;
+        if total == 0 {
+            return 0.0;
+        }
+        self.skipped as f64 / total as f64
+    }
+}
+
+/// What to do with rows describing the same physical flight (same route,
+/// cabin and times), e.g. codeshare listings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Keep only the cheapest listing.
+    KeepCheapest,
+    /// Keep the first listing and record the other airlines as codeshares.
+    MergeAirlines,
+}
+
+/// Prices a flight the source data has no fare for.
+pub trait CostModel: Send + Sync {
+    /// Cost of `leg`, which is complete apart from its `cost`.
+    fn cost(&self, leg: &FlightDTO) -> i64;
+}
+
+/// Prices a flight at its distance.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DistanceCostModel;
+
+impl CostModel for DistanceCostModel {
+    fn cost(&self, leg: &FlightDTO) -> i64 {
+        leg.distance as i64
+    }
+}
+
+/// Zero-based positions of the fields the importer reads. The defaults
+/// match the BTS on-time performance export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnMap {
+    pub flight_date: usize,
+    pub airline: usize,
+    pub flight_number: usize,
+    pub origin_id: usize,
+    pub origin_code: usize,
+    pub dest_id: usize,
+    pub dest_code: usize,
+    pub departure_time: usize,
+    pub arrival_time: usize,
+    pub distance: usize,
+    /// Scheduled elapsed time in minutes (CRSElapsedTime in BTS exports).
+    /// Without it an arrival time earlier than the departure time is taken
+    /// to be on the next day, which is wrong for flights of a day or more.
+    pub elapsed_time: Option<usize>,
+    /// Ticket price. Without it the importer's `CostModel` prices the
+    /// flight.
+    pub fare: Option<usize>,
+    /// Cabin the fare is for: "Economy", "Premium", "Business" or "First",
+    /// in any case. Rows without one are economy.
+    pub cabin: Option<usize>,
+}
+
+impl Default for ColumnMap {
+    fn default() -> Self {
+        ColumnMap {
+            flight_date: 5,
+            airline: 6,
+            flight_number: 10,
+            origin_id: 20,
+            origin_code: 23,
+            dest_id: 29,
+            dest_code: 32,
+            departure_time: 38,
+            arrival_time: 49,
+            distance: 63,
+            elapsed_time: None,
+            fare: None,
+            cabin: None,
+        }
+    }
 }
 
 pub struct CsvFlightImporter<'a> {
     airports_container: &'a AirportsContainer,
+    dedup: Option<DedupPolicy>,
+    columns: ColumnMap,
+    cost_model: Box<dyn CostModel>,
 }
 
 impl<'a> CsvFlightImporter<'a> {
     pub fn new(airports_container: &'a AirportsContainer) -> Self {
         CsvFlightImporter {
             airports_container,
+            dedup: None,
+            columns: ColumnMap::default(),
+            cost_model: Box::new(DistanceCostModel),
+        }
+    }
+
+    pub fn with_columns(mut self, columns: ColumnMap) -> Self {
+        self.columns = columns;
+        self
+    }
+
+    pub fn with_dedup(mut self, policy: DedupPolicy) -> Self {
+        self.dedup = Some(policy);
+        self
+    }
+
+    /// Prices rows without a fare with `model` instead of by distance.
+    pub fn with_cost_model(mut self, model: Box<dyn CostModel>) -> Self {
+        self.cost_model = model;
+        self
+    }
+
+    /// Adds the flights in `file_path` to those already loaded, e.g. a daily
+    /// delta file, numbering them on from the highest flight id loaded.
+    /// With `skip_existing`, rows for a flight already loaded (same
+    /// `FlightKey`: route, departure, airline and cabin) are left out and
+    /// counted in `ImportReport::already_loaded`.
+    pub fn import_flights_append(
+        &self,
+        file_path: &str,
+        skip_existing: bool,
+    ) -> Result<ImportReport, Box<dyn Error>> {
+        self.import_from(
+            file_path,
+            next_flight_id(self.airports_container),
+            skip_existing,
+        )
+    }
+
+    // Imports `file_path`, numbering flights from `first_flight_id`. With
+    // `skip_existing`, rows for flights already loaded are left out.
+    fn import_from(
+        &self,
+        file_path: &str,
+        first_flight_id: usize,
+        skip_existing: bool,
+    ) -> Result<ImportReport, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        // Flexible so short rows reach the per-column checks instead of
+        // failing the whole import.
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+        let mut report = ImportReport::default();
+        let mut next_flight_id = first_flight_id;
+
+        // With dedup enabled, flights are held back until the whole file is
+        // read so later duplicates can still be merged into them.
+        let mut pending: Vec<FlightDTO> = vec![];
+        let mut seen: HashMap<(usize, usize, String, String, Cabin), usize> = HashMap::new();
+
+        for result in rdr.records() {
+            let record = match result {
+                Ok(record) => record,
+                Err(error) => {
+                    let line = error.position().map(|x| x.line());
+                    report.skip(line, error.to_string());
+                    continue;
+                }
+            };
+            let flight_dto = match self.parse_record(&record, next_flight_id) {
+                Ok(flight_dto) => flight_dto,
+                Err(reason) => {
+                    report.skip(record.position().map(|x| x.line()), reason);
+                    continue;
+                }
+            };
+            if skip_existing {
+                let flights = self.airports_container.flights_container.read().unwrap();
+                if flights.find_by_key(&flight_dto.key()).is_some() {
+                    report.already_loaded += 1;
+                    continue;
+                }
+            }
+
+            match self.dedup {
+                None => {
+                    self.airports_container.add_flight(flight_dto);
+                    report.imported += 1;
+                }
+                Some(policy) => {
+                    let key = (
+                        flight_dto.from,
+                        flight_dto.to,
+                        flight_dto.departure_date.clone(),
+                        flight_dto.arrival_date.clone(),
+                        flight_dto.cabin,
+                    );
+                    if let Some(&index) = seen.get(&key) {
+                        Self::merge_duplicate(&mut pending[index], flight_dto, policy);
+                        continue;
+                    }
+                    seen.insert(key, pending.len());
+                    pending.push(flight_dto);
+                }
+            }
+            next_flight_id += 1;
+        }
+
+        for flight_dto in pending {
+            self.airports_container.add_flight(flight_dto);
+            report.imported += 1;
         }
+
+        Ok(report)
+    }
+
+    // Field `index` of `record`, or the reason the row has to be skipped
+    // when it is too short to have it.
+    fn column<'r>(record: &'r StringRecord, index: usize, name: &str) -> Result<&'r str, String> {
+        record
+            .get(index)
+            .ok_or_else(|| format!("missing column {}", name))
     }
 
-    // Parse time from HHMM format and combine with flight date
-    fn format_datetime(flight_date: &str, time_str: &str) -> String {
-        if time_str.len() < 2 {
-            return format!("{} 00:00:00", flight_date);
+    // The cabin named by `value`; empty means economy.
+    fn parse_cabin(value: &str) -> Result<Cabin, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "economy" => Ok(Cabin::Economy),
+            "premium" => Ok(Cabin::Premium),
+            "business" => Ok(Cabin::Business),
+            "first" => Ok(Cabin::First),
+            _ => Err(format!("unknown cabin {:?}", value)),
         }
+    }
 
+    fn merge_duplicate(existing: &mut FlightDTO, duplicate: FlightDTO, policy: DedupPolicy) {
+        match policy {
+            DedupPolicy::KeepCheapest => {
+                if duplicate.cost < existing.cost {
+                    *existing = FlightDTO {
+                        flight_id: existing.flight_id,
+                        ..duplicate
+                    };
+                }
+            }
+            DedupPolicy::MergeAirlines => {
+                if duplicate.airline != existing.airline
+                    && !existing.codeshares.contains(&duplicate.airline)
+                {
+                    existing.codeshares.push(duplicate.airline);
+                }
+            }
+        }
+    }
+
+    // Combines `flight_date` with a time in HHMM form. Shorter times are
+    // zero-padded on the left, so "930" is 09:30 and "5" is 00:05. 2400 is
+    // kept as 24:00, which `FlightDTO` rolls over to the next day.
+    fn format_datetime(flight_date: &str, time_str: &str) -> Result<String, String> {
         let time_digits = time_str.trim();
-        let (hours, minutes) = if time_digits.len() <= 2 {
-            (time_digits, "00")
-        } else if time_digits.len() == 3 {
-            ("0", &time_digits[0..1])
-        } else {
-            // Format HHMM -> HH:MM
-            let len = time_digits.len();
-            (&time_digits[0..len-2], &time_digits[len-2..])
-        };
+        if time_digits.is_empty()
+            || time_digits.len() > 4
+            || !time_digits.bytes().all(|x| x.is_ascii_digit())
+        {
+            return Err(format!("malformed time {:?}", time_str));
+        }
+
+        let padded = format!("{:0>4}", time_digits);
+        let hours: u32 = padded[0..2].parse().unwrap();
+        let minutes: u32 = padded[2..4].parse().unwrap();
+        if minutes > 59 || hours > 24 || (hours == 24 && minutes != 0) {
+            return Err(format!("time out of range {:?}", time_str));
+        }
 
-        format!("{} {}:{}:00", flight_date, hours, minutes)
+        Ok(format!("{} {:02}:{:02}:00", flight_date, hours, minutes))
     }
 }
 
-impl<'a> FlightImporter for CsvFlightImporter<'a> {
-    fn import_flights(&self, file_path: &str) -> Result<usize, Box<dyn Error>> {
-        let file = File::open(file_path)?;
-        let mut rdr = csv::Reader::from_reader(file);
-        let mut flights_added = 0;
-        let mut next_flight_id = 0;
+impl<'a> CsvFlightImporter<'a> {
+    // Turns one row into a flight, adding any airports it mentions.
+    fn parse_record(&self, record: &StringRecord, flight_id: usize) -> Result<FlightDTO, String> {
+        let columns = &self.columns;
+
+        // Extract flight data from CSV
+        // OriginAirportID
+        let origin_id = Self::column(record, columns.origin_id, "OriginAirportID")?
+            .parse::<usize>()
+            .map_err(|_| "origin_id parse failed".to_string())?;
 
-        // Get the max flight ID to avoid duplicates
-        if let Ok(flights_container) = self.airports_container.flights_container.read() {
-            next_flight_id = flights_container.flights.len();
+        // DestAirportID
+        let dest_id = Self::column(record, columns.dest_id, "DestAirportID")?
+            .parse::<usize>()
+            .map_err(|_| "dest_id parse failed".to_string())?;
+
+        if dest_id == origin_id {
+            return Err("same origin and destination".to_string());
         }
 
-        for result in rdr.records() {
-            let record = result?;
-            
-            // Skip if the record doesn't have enough fields
-            // if record.len() < 85 {
-            //     continue;
-            // }
-
-            // Extract flight data from CSV
-            // OriginAirportID (column 20)
-            let origin_id = match record[20].parse::<usize>() {
-                Ok(id) => id,
-                Err(_) => continue,
+        // Origin and destination airport codes
+        let origin_code = Self::column(record, columns.origin_code, "Origin")?.to_string();
+        let dest_code = Self::column(record, columns.dest_code, "Dest")?.to_string();
+
+        // Marketing_Airline_Network and Flight_Number_Marketing_Airline
+        let airline = record
+            .get(columns.airline)
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let flight_number = record
+            .get(columns.flight_number)
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+
+        // Flight date
+        let flight_date = Self::column(record, columns.flight_date, "FlightDate")?.trim();
+        if flight_date.is_empty() {
+            return Err("empty flight date".to_string());
+        }
+
+        // CRSDepTime and CRSArrTime
+        let dep_time = Self::column(record, columns.departure_time, "CRSDepTime")?.trim();
+        let arr_time = Self::column(record, columns.arrival_time, "CRSArrTime")?.trim();
+        if dep_time.is_empty() || arr_time.is_empty() {
+            return Err("empty departure or arrival time".to_string());
+        }
+
+        // Distance
+        let distance = Self::column(record, columns.distance, "Distance")?
+            .parse::<i32>()
+            .unwrap_or_default();
+
+        // Fare, when the row has one; otherwise the cost model prices the
+        // flight below
+        let fare = columns
+            .fare
+            .and_then(|index| record.get(index))
+            .and_then(|fare| fare.trim().parse::<f64>().ok());
+
+        let cabin = match columns.cabin.and_then(|index| record.get(index)) {
+            Some(cabin) => Self::parse_cabin(cabin)?,
+            None => Cabin::Economy,
+        };
+
+        let duration_minutes = columns
+            .elapsed_time
+            .and_then(|index| record.get(index))
+            .and_then(|minutes| minutes.trim().parse::<f64>().ok())
+            .map(|minutes| minutes.round() as i64);
+
+        // Create properly formatted date strings
+        let departure_date = Self::format_datetime(flight_date, dep_time)?;
+        let arrival_date = Self::format_datetime(flight_date, arr_time)?;
+
+        // Ensure both airports exist
+        if !self.airports_container.has_airport(origin_id) {
+            let airport = Airport {
+                id: origin_id,
+                name: origin_code,
+                latitude: 0.0,
+                longitude: 0.0,
+                timezone: Tz::UTC,
+                outgoing: std::collections::BTreeMap::new(),
             };
+            self.airports_container.add_airport(airport);
+        }
 
-            // DestAirportID (column 30)
-            let dest_id = match record[29].parse::<usize>() {
-                Ok(id) => id,
-                Err(_) => continue,
+        if !self.airports_container.has_airport(dest_id) {
+            let airport = Airport {
+                id: dest_id,
+                name: dest_code,
+                latitude: 0.0,
+                longitude: 0.0,
+                timezone: Tz::UTC,
+                outgoing: std::collections::BTreeMap::new(),
             };
+            self.airports_container.add_airport(airport);
+        }
 
-            if dest_id == origin_id {
-                println!("Skipping flight with same origin and destination: {}", origin_id);
-                continue;
-            }
+        let mut flight = FlightDTO {
+            flight_id,
+            from: origin_id,
+            to: dest_id,
+            cost: 0,
+            currency: DEFAULT_CURRENCY.to_string(),
+            distance,
+            cabin,
+            seats: UNLIMITED_SEATS,
+            airline,
+            flight_number,
+            codeshares: vec![],
+            arrival_date,
+            departure_date,
+            duration_minutes,
+        };
+        flight.cost = match fare {
+            Some(fare) => fare.round() as i64,
+            None => self.cost_model.cost(&flight),
+        };
+        Ok(flight)
+    }
+}
+
+impl<'a> FlightImporter for CsvFlightImporter<'a> {
+    fn import_with_report(&self, file_path: &str) -> Result<ImportReport, Box<dyn Error>> {
+        self.import_from(file_path, next_flight_id(self.airports_container), false)
+    }
+}
+
+/// Reads newline-delimited JSON, one `FlightDTO` object per line. Flight
+/// ids in the file are ignored and assigned in order, as for CSV.
+pub struct JsonFlightImporter<'a> {
+    airports_container: &'a AirportsContainer,
+}
+
+impl<'a> JsonFlightImporter<'a> {
+    pub fn new(airports_container: &'a AirportsContainer) -> Self {
+        JsonFlightImporter { airports_container }
+    }
+}
+
+// Adds the airport with `id` unless it exists. Files that carry no airport
+// details name it after its id.
+fn ensure_airport(airports_container: &AirportsContainer, id: usize) {
+    if !airports_container.has_airport(id) {
+        airports_container.add_airport(Airport {
+            id,
+            name: id.to_string(),
+            latitude: 0.0,
+            longitude: 0.0,
+            timezone: Tz::UTC,
+            outgoing: std::collections::BTreeMap::new(),
+        });
+    }
+}
+
+// First id free for new flights: one past the highest loaded. The flight
+// count is no guide, as removed flights leave gaps below the highest id.
+fn next_flight_id(airports_container: &AirportsContainer) -> usize {
+    airports_container
+        .flights_container
+        .read()
+        .unwrap()
+        .max_flight_id()
+        .map_or(0, |x| x + 1)
+}
 
-            // Origin and destination airport codes
-            let origin_code = record[23].to_string();
-            let dest_code = record[32].to_string();
+impl<'a> FlightImporter for JsonFlightImporter<'a> {
+    fn import_with_report(&self, file_path: &str) -> Result<ImportReport, Box<dyn Error>> {
+        let reader = BufReader::new(File::open(file_path)?);
+        let mut report = ImportReport::default();
+        let mut next_flight_id = next_flight_id(self.airports_container);
 
-            // println!("adding flight: {}, {}, {}, {}", origin_id, dest_id, origin_code, dest_code);
-            
-            // Flight date (column 6)
-            let flight_date = record[5].trim();
-            if flight_date.is_empty() {
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = Some(index as u64 + 1);
+            if line.trim().is_empty() {
                 continue;
             }
-
-            // CRSDepTime (column 39) and CRSArrTime (column 47)
-            let dep_time = record[38].trim();
-            let arr_time = record[49].trim();
-            // println!("dep_time: {}, arr_time: {}", dep_time, arr_time);
-            if dep_time.is_empty() || arr_time.is_empty() {
+            let flight_dto: FlightDTO = match serde_json::from_str(&line) {
+                Ok(flight_dto) => flight_dto,
+                Err(error) => {
+                    report.skip(line_number, error.to_string());
+                    continue;
+                }
+            };
+            if flight_dto.from == flight_dto.to {
+                report.skip(line_number, "same origin and destination".to_string());
+                continue;
+            }
+            if let Err(reason) = flight_dto.check_dates() {
+                report.skip(line_number, reason);
                 continue;
             }
 
-            // Distance (column 86)
-            let distance = match record[63].parse::<i32>() {
-                Ok(d) => d,
-                Err(_) => 0,
-            };
+            ensure_airport(self.airports_container, flight_dto.from);
+            ensure_airport(self.airports_container, flight_dto.to);
+            self.airports_container.add_flight(FlightDTO {
+                flight_id: next_flight_id,
+                ..flight_dto
+            });
+            report.imported += 1;
+            next_flight_id += 1;
+        }
 
-            // Calculate cost based on distance (simple approach)
-            let cost = distance;
-
-            // Create properly formatted date strings
-            let departure_date = Self::format_datetime(flight_date, dep_time);
-            let arrival_date = Self::format_datetime(flight_date, arr_time);
-            // println!("adding flight: {}, {}, {}, {}, {}, {}", origin_id, dest_id, arrival_date, departure_date, origin_code, dest_code);
-
-            // let has_airport = self.airports_container.has_airport(origin_id);
-            // println!("has_airport: {}, {}", origin_id, has_airport);
-            // Ensure both airports exist
-            if !self.airports_container.has_airport(origin_id) {
-                let airport = Airport {
-                    id: origin_id,
-                    name: origin_code,
-                    outgoing: std::collections::BTreeMap::new(),
-                };
-                self.airports_container.add_airport(airport);
+        Ok(report)
+    }
+}
+
+// One row of a GTFS stop_times file.
+struct StopTime {
+    line: Option<u64>,
+    sequence: u32,
+    airport: usize,
+    arrival: NaiveDateTime,
+    departure: NaiveDateTime,
+}
+
+/// Reads a GTFS `stop_times.txt`, whose columns are found by their header
+/// names: trip_id, arrival_time, departure_time, stop_id and
+/// stop_sequence. Every pair of consecutive stops on a trip becomes one
+/// flight, leaving the first at its departure time and reaching the second
+/// at its arrival time. Times count from midnight on `service_date` and may
+/// pass 24:00:00.
+///
+/// A stop_id is the code of a known airport, or else an airport id. Flights
+/// are priced by the `CostModel` from the great-circle distance between
+/// their stops.
+pub struct GtfsFlightImporter<'a> {
+    airports_container: &'a AirportsContainer,
+    service_date: NaiveDate,
+    cost_model: Box<dyn CostModel>,
+}
+
+impl<'a> GtfsFlightImporter<'a> {
+    pub fn new(airports_container: &'a AirportsContainer, service_date: NaiveDate) -> Self {
+        GtfsFlightImporter {
+            airports_container,
+            service_date,
+            cost_model: Box::new(DistanceCostModel),
+        }
+    }
+
+    pub fn with_cost_model(mut self, model: Box<dyn CostModel>) -> Self {
+        self.cost_model = model;
+        self
+    }
+
+    // "HH:MM:SS" on the service day; hours may be 24 or more for trips
+    // running past midnight.
+    fn service_time(&self, time: &str) -> Result<NaiveDateTime, String> {
+        let parts: Vec<&str> = time.trim().split(':').collect();
+        let [hours, minutes, seconds] = parts[..] else {
+            return Err(format!("malformed time {:?}", time));
+        };
+        let parse = |x: &str| {
+            x.parse::<i64>()
+                .map_err(|_| format!("malformed time {:?}", time))
+        };
+        let (hours, minutes, seconds) = (parse(hours)?, parse(minutes)?, parse(seconds)?);
+        if minutes > 59 || seconds > 59 {
+            return Err(format!("time out of range {:?}", time));
+        }
+        Ok(self.service_date.and_hms_opt(0, 0, 0).unwrap()
+            + Duration::seconds(hours * 3600 + minutes * 60 + seconds))
+    }
+
+    fn stop_airport(&self, stop_id: &str) -> Result<usize, String> {
+        let stop_id = stop_id.trim();
+        if let Some(id) = self.airports_container.get_airport_by_code(stop_id) {
+            return Ok(id);
+        }
+        let id = stop_id
+            .parse::<usize>()
+            .map_err(|_| format!("unknown stop {:?}", stop_id))?;
+        ensure_airport(self.airports_container, id);
+        Ok(id)
+    }
+
+    fn parse_record(
+        &self,
+        record: &StringRecord,
+        columns: &[usize; 5],
+    ) -> Result<(String, StopTime), String> {
+        let [trip_id, arrival, departure, stop_id, sequence] =
+            columns.map(|index| record.get(index).unwrap_or_default());
+        if trip_id.trim().is_empty() {
+            return Err("empty trip_id".to_string());
+        }
+        let stop = StopTime {
+            line: record.position().map(|x| x.line()),
+            sequence: sequence
+                .trim()
+                .parse()
+                .map_err(|_| format!("malformed stop_sequence {:?}", sequence))?,
+            airport: self.stop_airport(stop_id)?,
+            arrival: self.service_time(arrival)?,
+            departure: self.service_time(departure)?,
+        };
+        Ok((trip_id.trim().to_string(), stop))
+    }
+}
+
+impl<'a> FlightImporter for GtfsFlightImporter<'a> {
+    fn import_with_report(&self, file_path: &str) -> Result<ImportReport, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(File::open(file_path)?);
+        let headers = rdr.headers()?.clone();
+        let mut columns = [0; 5];
+        for (column, name) in columns.iter_mut().zip([
+            "trip_id",
+            "arrival_time",
+            "departure_time",
+            "stop_id",
+            "stop_sequence",
+        ]) {
+            *column = headers
+                .iter()
+                .position(|x| x.trim() == name)
+                .ok_or_else(|| format!("missing column {}", name))?;
+        }
+        let mut report = ImportReport::default();
+
+        // Trips in the order they first appear, so flight ids follow the
+        // file.
+        let mut trips: Vec<(String, Vec<StopTime>)> = vec![];
+        let mut trip_index: HashMap<String, usize> = HashMap::new();
+        for result in rdr.records() {
+            let record = match result {
+                Ok(record) => record,
+                Err(error) => {
+                    report.skip(error.position().map(|x| x.line()), error.to_string());
+                    continue;
+                }
+            };
+            match self.parse_record(&record, &columns) {
+                Ok((trip_id, stop)) => {
+                    let index = *trip_index.entry(trip_id.clone()).or_insert_with(|| {
+                        trips.push((trip_id, vec![]));
+                        trips.len() - 1
+                    });
+                    trips[index].1.push(stop);
+                }
+                Err(reason) => report.skip(record.position().map(|x| x.line()), reason),
             }
+        }
 
-            if !self.airports_container.has_airport(dest_id) {
-                let airport = Airport {
-                    id: dest_id,
-                    name: dest_code,
-                    outgoing: std::collections::BTreeMap::new(),
+        let mut next_flight_id = next_flight_id(self.airports_container);
+        for (trip_id, mut stops) in trips {
+            stops.sort_by_key(|x| x.sequence);
+            for pair in stops.windows(2) {
+                let (from, to) = (&pair[0], &pair[1]);
+                if from.airport == to.airport {
+                    report.skip(to.line, "same origin and destination".to_string());
+                    continue;
+                }
+                if to.arrival < from.departure {
+                    report.skip(
+                        to.line,
+                        "arrives before the previous stop departs".to_string(),
+                    );
+                    continue;
+                }
+                let distance = self
+                    .airports_container
+                    .haversine_distance_km(from.airport, to.airport)
+                    .unwrap_or_default()
+                    .round() as i32;
+                let mut flight = FlightDTO {
+                    flight_id: next_flight_id,
+                    from: from.airport,
+                    to: to.airport,
+                    cost: 0,
+                    currency: DEFAULT_CURRENCY.to_string(),
+                    distance,
+                    cabin: Cabin::Economy,
+                    seats: UNLIMITED_SEATS,
+                    airline: String::new(),
+                    flight_number: trip_id.clone(),
+                    codeshares: vec![],
+                    arrival_date: to.arrival.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    departure_date: from.departure.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    duration_minutes: Some((to.arrival - from.departure).num_minutes()),
                 };
-                self.airports_container.add_airport(airport);
+                flight.cost = self.cost_model.cost(&flight);
+                self.airports_container.add_flight(flight);
+                report.imported += 1;
+                next_flight_id += 1;
             }
+        }
 
-            // Create and add the flight
-            let flight_dto = FlightDTO {
-                flight_id: next_flight_id,
-                from: origin_id,
-                to: dest_id,
-                cost,
-                arrival_date,
-                departure_date,
-            };
+        Ok(report)
+    }
+}
 
-            self.airports_container.add_flight(flight_dto);
-            next_flight_id += 1;
-            flights_added += 1;
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::flight::FlightEdge;
+    use crate::search::Search;
+    use crate::testutil::{flight, graph, ids, query, temp_file};
+
+    #[test]
+    fn json_import_counts_valid_lines_and_reports_the_rest() {
+        let line = |flight: FlightDTO| serde_json::to_string(&flight).unwrap();
+        let contents = [
+            line(flight(7, 1, 2, 100, "08:00", "09:00")),
+            "{not json".to_string(),
+            line(flight(7, 1, 1, 100, "08:00", "09:00")),
+            line(FlightDTO {
+                arrival_date: "bad".to_string(),
+                ..flight(7, 1, 2, 100, "08:00", "09:00")
+            }),
+            String::new(),
+            line(flight(7, 2, 3, 100, "10:00", "11:00")),
+        ]
+        .join("\n");
+        let path = temp_file("import.jsonl", &contents);
+        let airports = AirportsContainer::new();
+
+        let report = JsonFlightImporter::new(&airports)
+            .import_with_report(path.to_str().unwrap())
+            .unwrap();
+
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.skipped, 3);
+        let lines: Vec<_> = report.errors.iter().map(|x| x.line).collect();
+        assert_eq!(lines, vec![Some(2), Some(3), Some(4)]);
+        assert!(report.errors[2].reason.contains("arrival_date"));
+        assert_eq!(airports.stats().num_flights, 2);
+    }
+
+    #[test]
+    fn imports_number_flights_past_the_highest_id() {
+        let airports = graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+                flight(2, 2, 0, 100, "12:00", "13:00"),
+            ],
+        );
+        // Leaves flight 1 alone, one flight below the highest id.
+        airports.remove_airport(0);
+        let line = serde_json::to_string(&flight(0, 2, 1, 100, "14:00", "15:00")).unwrap();
+        let path = temp_file("append.jsonl", &line);
+
+        JsonFlightImporter::new(&airports)
+            .import_with_report(path.to_str().unwrap())
+            .unwrap();
+
+        let flights = airports.flights_container.read().unwrap();
+        assert_eq!(flights.get_flight(1).unwrap().from.read().unwrap().id, 1);
+        assert_eq!(flights.get_flight(2).unwrap().from.read().unwrap().id, 2);
+        drop(flights);
+        assert_eq!(airports.check_index_consistency(), Ok(()));
+    }
+
+    const HEADER: &str =
+        "FlightDate,Airline,Number,OriginID,Origin,DestID,Dest,Dep,Arr,Distance,Fare";
+
+    // One column per field, in `HEADER` order.
+    fn compact_columns() -> ColumnMap {
+        ColumnMap {
+            flight_date: 0,
+            airline: 1,
+            flight_number: 2,
+            origin_id: 3,
+            origin_code: 4,
+            dest_id: 5,
+            dest_code: 6,
+            departure_time: 7,
+            arrival_time: 8,
+            distance: 9,
+            elapsed_time: None,
+            fare: Some(10),
+            cabin: None,
+        }
+    }
+
+    // A CSV file of `rows` under `HEADER`.
+    fn csv_file(name: &str, rows: &[&str]) -> String {
+        let contents = format!("{}\n{}\n", HEADER, rows.join("\n"));
+        temp_file(name, &contents).to_str().unwrap().to_string()
+    }
+
+    fn csv_importer(airports: &AirportsContainer) -> CsvFlightImporter<'_> {
+        CsvFlightImporter::new(airports).with_columns(compact_columns())
+    }
+
+    const AA_JFK_LAX: &str = "2024-01-14,AA,100,1,JFK,2,LAX,0800,1100,2475,300";
+    const BA_JFK_LAX: &str = "2024-01-14,BA,200,1,JFK,2,LAX,0800,1100,2475,250";
+    const UA_JFK_ORD: &str = "2024-01-14,UA,300,1,JFK,3,ORD,0700,0900,740,90";
+    const UA_ORD_LAX: &str = "2024-01-14,UA,301,3,ORD,2,LAX,1000,1230,1745,120";
+
+    // Every flight loaded, by id.
+    fn loaded(airports: &AirportsContainer) -> Vec<Arc<FlightEdge>> {
+        let flights = airports.flights_container.read().unwrap();
+        let mut loaded: Vec<_> = flights.flights.iter().map(|x| x.clone()).collect();
+        loaded.sort_by_key(|x| x.flight_id);
+        loaded
+    }
+
+    #[test]
+    fn dedup_collapses_listings_of_one_physical_flight() {
+        let path = csv_file("codeshare.csv", &[AA_JFK_LAX, BA_JFK_LAX]);
+
+        let airports = AirportsContainer::new();
+        assert_eq!(csv_importer(&airports).import_flights(&path).unwrap(), 2);
+
+        let airports = AirportsContainer::new();
+        let importer = csv_importer(&airports).with_dedup(DedupPolicy::KeepCheapest);
+        assert_eq!(importer.import_flights(&path).unwrap(), 1);
+        let flights = loaded(&airports);
+        assert_eq!((flights[0].airline.as_str(), flights[0].cost), ("BA", 250));
+
+        let airports = AirportsContainer::new();
+        let importer = csv_importer(&airports).with_dedup(DedupPolicy::MergeAirlines);
+        assert_eq!(importer.import_flights(&path).unwrap(), 1);
+        let flights = loaded(&airports);
+        assert_eq!(flights[0].airline, "AA");
+        assert_eq!(flights[0].codeshares, vec!["BA".to_string()]);
+    }
+
+    #[test]
+    fn short_rows_are_skipped_and_reported() {
+        let path = csv_file("short.csv", &[AA_JFK_LAX, "2024-01-14,AA,101,1,JFK"]);
+        let airports = AirportsContainer::new();
+
+        let report = csv_importer(&airports).import_with_report(&path).unwrap();
+
+        assert_eq!((report.imported, report.skipped), (1, 1));
+        assert_eq!(
+            report.errors,
+            vec![ImportError {
+                line: Some(3),
+                reason: "missing column DestAirportID".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn fares_price_flights_apart_from_their_distance() {
+        let unpriced = "2024-01-14,AA,101,2,LAX,1,JFK,1200,2000,2475,";
+        let path = csv_file("fares.csv", &[AA_JFK_LAX, unpriced]);
+        let airports = AirportsContainer::new();
+        csv_importer(&airports).import_flights(&path).unwrap();
+
+        let flights = loaded(&airports);
+        assert_eq!((flights[0].cost, flights[0].distance), (300, 2475));
+        assert_eq!((flights[1].cost, flights[1].distance), (2475, 2475));
+    }
+
+    // Set in the child process `imports_write_nothing_to_stdout` starts.
+    const QUIET_IMPORT_CHILD: &str = "RUST_TEST_QUIET_IMPORT_CHILD";
+
+    // Imports a file with bad rows between two markers. Only does anything
+    // when run as the child of `imports_write_nothing_to_stdout`, which
+    // checks nothing was printed between the markers.
+    #[test]
+    fn quiet_import_child() {
+        if std::env::var_os(QUIET_IMPORT_CHILD).is_none() {
+            return;
         }
+        let path = csv_file("quiet.csv", &[AA_JFK_LAX, "2024-01-14,AA", "not,a,row"]);
+        let airports = AirportsContainer::new();
+        print!("<import>");
+        std::io::stdout().flush().unwrap();
+        csv_importer(&airports).import_with_report(&path).unwrap();
+        std::io::stdout().flush().unwrap();
+        print!("</import>");
+    }
+
+    #[test]
+    fn imports_write_nothing_to_stdout() {
+        // Test output capture only sees `print!`, so the import runs in a
+        // child process whose real stdout is inspected.
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "import::tests::quiet_import_child",
+                "--nocapture",
+            ])
+            .env(QUIET_IMPORT_CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("<import></import>"), "{}", stdout);
+    }
+
+    #[test]
+    fn reports_why_each_row_was_skipped() {
+        let path = csv_file(
+            "report.csv",
+            &[
+                AA_JFK_LAX,
+                "2024-01-14,AA,101,x,JFK,2,LAX,0800,1100,2475,300",
+                "2024-01-14,AA,102,1,JFK,1,JFK,0800,1100,2475,300",
+                ",AA,103,1,JFK,2,LAX,0800,1100,2475,300",
+            ],
+        );
+        let airports = AirportsContainer::new();
+
+        let report = csv_importer(&airports).import_with_report(&path).unwrap();
+
+        assert_eq!((report.imported, report.skipped), (1, 3));
+        let reasons: Vec<(Option<u64>, &str)> = report
+            .errors
+            .iter()
+            .map(|x| (x.line, x.reason.as_str()))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (Some(3), "origin_id parse failed"),
+                (Some(4), "same origin and destination"),
+                (Some(5), "empty flight date"),
+            ]
+        );
+        assert_eq!(report.skipped_fraction(), 0.75);
+    }
+
+    fn hhmm(time: &str) -> Result<String, String> {
+        CsvFlightImporter::format_datetime("2024-01-14", time)
+    }
+
+    #[test]
+    fn hhmm_times_pad_to_four_digits() {
+        assert_eq!(hhmm("5").unwrap(), "2024-01-14 00:05:00");
+        assert_eq!(hhmm("45").unwrap(), "2024-01-14 00:45:00");
+        assert_eq!(hhmm("930").unwrap(), "2024-01-14 09:30:00");
+        assert_eq!(hhmm("1745").unwrap(), "2024-01-14 17:45:00");
+        assert_eq!(hhmm(" 0800 ").unwrap(), "2024-01-14 08:00:00");
+    }
+
+    #[test]
+    fn hhmm_2400_rolls_over_to_the_next_day() {
+        let time = hhmm("2400").unwrap();
+        assert_eq!(time, "2024-01-14 24:00:00");
+        let rolled = FlightDTO {
+            departure_date: time,
+            ..flight(0, 0, 1, 100, "00:00", "00:00")
+        };
+        assert_eq!(
+            rolled.departure_date(),
+            NaiveDate::from_ymd_opt(2024, 1, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn hhmm_rejects_malformed_and_out_of_range_times() {
+        for time in ["", "12345", "9a0", "-930", "2401", "2500", "0960"] {
+            assert!(hhmm(time).is_err(), "{:?}", time);
+        }
+    }
+
+    // Prices at distance, doubled for one airline.
+    struct Surcharged(&'static str);
+
+    impl CostModel for Surcharged {
+        fn cost(&self, leg: &FlightDTO) -> i64 {
+            let cost = DistanceCostModel.cost(leg);
+            if leg.airline == self.0 {
+                cost * 2
+            } else {
+                cost
+            }
+        }
+    }
+
+    #[test]
+    fn cost_models_price_flights_without_a_fare() {
+        let path = csv_file("unpriced.csv", &[AA_JFK_LAX, BA_JFK_LAX]);
+        let unpriced = ColumnMap {
+            fare: None,
+            ..compact_columns()
+        };
+        let costs = |airports: &AirportsContainer| -> Vec<(String, i64)> {
+            let flights = loaded(airports);
+            flights
+                .iter()
+                .map(|x| (x.airline.clone(), x.cost))
+                .collect()
+        };
+
+        let airports = AirportsContainer::new();
+        let importer = CsvFlightImporter::new(&airports).with_columns(unpriced.clone());
+        importer.import_flights(&path).unwrap();
+        let by_distance = vec![("AA".to_string(), 2475), ("BA".to_string(), 2475)];
+        assert_eq!(costs(&airports), by_distance);
+
+        let airports = AirportsContainer::new();
+        let importer = CsvFlightImporter::new(&airports)
+            .with_columns(unpriced)
+            .with_cost_model(Box::new(Surcharged("BA")));
+        importer.import_flights(&path).unwrap();
+        let surcharged = vec![("AA".to_string(), 2475), ("BA".to_string(), 4950)];
+        assert_eq!(costs(&airports), surcharged);
+    }
+
+    #[test]
+    fn gtfs_trips_become_one_flight_per_pair_of_stops() {
+        // Listed out of sequence; the last stop is reached after midnight.
+        let path = temp_file(
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence\n\
+             T1,09:30:00,10:00:00,11,2\n\
+             T1,08:00:00,08:00:00,10,1\n\
+             T1,24:30:00,24:30:00,12,3\n",
+        );
+        let airports = AirportsContainer::new();
+        let service_date = NaiveDate::from_ymd_opt(2024, 1, 14).unwrap();
+        let importer = GtfsFlightImporter::new(&airports, service_date);
+        let report = importer.import_with_report(path.to_str().unwrap()).unwrap();
+        assert_eq!((report.imported, report.skipped), (2, 0));
+
+        let on = |datetime| NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M").unwrap();
+        let legs: Vec<_> = loaded(&airports)
+            .iter()
+            .map(|x| {
+                let (from, to) = (x.from.read().unwrap().id, x.to.read().unwrap().id);
+                (from, to, x.depart_at, x.arrive_at, x.flight_number.clone())
+            })
+            .collect();
+        let trip = "T1".to_string();
+        assert_eq!(
+            legs,
+            vec![
+                (
+                    10,
+                    11,
+                    on("2024-01-14 08:00"),
+                    on("2024-01-14 09:30"),
+                    trip.clone()
+                ),
+                (11, 12, on("2024-01-14 10:00"), on("2024-01-15 00:30"), trip),
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshots_of_imported_csvs_search_the_same() {
+        let path = csv_file(
+            "snapshot.csv",
+            &[AA_JFK_LAX, BA_JFK_LAX, UA_JFK_ORD, UA_ORD_LAX],
+        );
+        let imported = AirportsContainer::new();
+        assert_eq!(csv_importer(&imported).import_flights(&path).unwrap(), 4);
+        let snapshot = temp_file("imported.snapshot", "");
+        let snapshot = snapshot.to_str().unwrap();
+        imported.save_snapshot(snapshot).unwrap();
+        let reloaded = AirportsContainer::new();
+        reloaded.load_snapshot(snapshot).unwrap();
+        assert_eq!(reloaded.check_index_consistency(), Ok(()));
+
+        let results = |airports: AirportsContainer| {
+            let found = Search::new(airports).find(query(1, 2, 2, 5)).unwrap();
+            let paths: Vec<_> = found.iter().map(|x| x.readable_path()).collect();
+            (ids(&found), paths)
+        };
+        let (ids, paths) = results(imported);
+        assert_eq!(ids.len(), 3);
+        assert_eq!(results(reloaded), (ids, paths));
+    }
+
+    #[test]
+    fn stats_describe_an_imported_file() {
+        let empty = AirportsContainer::new().stats();
+        assert_eq!((empty.num_airports, empty.num_flights), (0, 0));
+        assert_eq!(empty.earliest_departure, None);
+
+        let path = csv_file(
+            "stats.csv",
+            &[AA_JFK_LAX, BA_JFK_LAX, UA_JFK_ORD, UA_ORD_LAX],
+        );
+        let airports = AirportsContainer::new();
+        csv_importer(&airports).import_flights(&path).unwrap();
+        let stats = airports.stats();
+        assert_eq!((stats.num_airports, stats.num_flights), (3, 4));
+        let at = |time| NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").ok();
+        assert_eq!(stats.earliest_departure, at("2024-01-14 07:00"));
+        assert_eq!(stats.latest_departure, at("2024-01-14 10:00"));
+        // JFK flies to two airports, ORD to one and LAX to none.
+        assert_eq!(stats.avg_out_degree, 1.0);
+    }
+
+    #[test]
+    fn appends_add_only_new_flights() {
+        let base = csv_file("base.csv", &[AA_JFK_LAX, UA_JFK_ORD]);
+        let delta = csv_file(
+            "delta.csv",
+            &[AA_JFK_LAX, BA_JFK_LAX, UA_JFK_ORD, UA_ORD_LAX],
+        );
+        let airports = AirportsContainer::new();
+        let importer = csv_importer(&airports);
+        assert_eq!(importer.import_flights(&base).unwrap(), 2);
+        let report = importer.import_flights_append(&delta, true).unwrap();
+        assert_eq!((report.imported, report.already_loaded), (2, 2));
 
-        Ok(flights_added)
+        let flights = loaded(&airports);
+        let ids: Vec<usize> = flights.iter().map(|x| x.flight_id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3]);
+        let mut keys: Vec<_> = flights
+            .iter()
+            .map(|x| {
+                (
+                    x.from.read().unwrap().id,
+                    x.to.read().unwrap().id,
+                    x.depart_at,
+                    x.airline.clone(),
+                )
+            })
+            .collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), flights.len());
+        assert_eq!(airports.check_index_consistency(), Ok(()));
     }
 }
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDateTime};
+use serde::{Serialize, Serializer};
+
+use crate::currency::{CurrencyConverter, Money};
+use crate::flight::Cabin;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LegInfo {
+    pub flight_id: usize,
+    pub from_id: usize,
+    pub from_name: String,
+    pub to_id: usize,
+    pub to_name: String,
+    pub airline: String,
+    pub flight_number: String,
+    pub depart_at: NaiveDateTime,
+    pub arrive_at: NaiveDateTime,
+    /// Fare for one passenger.
+    pub cost: i64,
+    /// ISO 4217 code `cost` is in.
+    pub currency: String,
+    pub distance: i32,
+    pub cabin: Cabin,
+    /// Seats left in each cabin the physical flight is sold in.
+    pub seats_by_cabin: HashMap<Cabin, u32>,
+    /// CO₂ for one passenger.
+    pub estimated_emissions_kg: Option<f64>,
+}
+
+/// One bookable combination of fares over an itinerary's flights.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FareOption {
+    pub flight_ids: Vec<usize>,
+    pub cabins: Vec<Cabin>,
+    pub total_cost: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Itinerary {
+    pub legs: Vec<LegInfo>,
+    /// Airport the last leg lands at; tells searches with several possible
+    /// destinations which one was reached.
+    pub destination_id: usize,
+    /// Party size. Totals cover every passenger.
+    pub passengers: u32,
+    pub total_cost: i64,
+    /// First departure to last arrival, layovers included. Serialized as
+    /// whole minutes.
+    #[serde(serialize_with = "serialize_minutes")]
+    pub total_duration: Duration,
+    pub num_stops: usize,
+    /// CO₂ for the whole party.
+    pub estimated_emissions_kg: Option<f64>,
+    pub fare_options: Vec<FareOption>,
+    /// Chance of completing every leg without a misconnect.
+    pub completion_probability: f64,
+    /// Leg fares summed per currency, sorted by currency code.
+    pub native_totals: Vec<Money>,
+    /// Every leg's fare converted into the user's preferred currency, when
+    /// one was requested and the converter could convert it.
+    pub total_in_preferred: Option<Money>,
+}
+
+impl Itinerary {
+    // Identifies the physical flights flown, by route, times, airline and
+    // flight number, ignoring the cabin they were booked in.
+    fn routing(&self) -> Vec<(usize, usize, NaiveDateTime, NaiveDateTime, &str, &str)> {
+        self.legs
+            .iter()
+            .map(|leg| {
+                (
+                    leg.from_id,
+                    leg.to_id,
+                    leg.depart_at,
+                    leg.arrive_at,
+                    leg.airline.as_str(),
+                    leg.flight_number.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    /// Fills in per-leg and total emissions from `kg_per_distance`, the
+    /// CO₂ emitted per passenger per unit of `distance`. Like the fares,
+    /// legs are per passenger and the total covers every passenger.
+    pub fn with_emissions(mut self, kg_per_distance: f64) -> Self {
+        let mut total = 0.0;
+        for leg in self.legs.iter_mut() {
+            let emissions = leg.distance as f64 * kg_per_distance;
+            leg.estimated_emissions_kg = Some(emissions);
+            total += emissions;
+        }
+        self.estimated_emissions_kg = Some(total * self.passengers as f64);
+        self
+    }
+
+    /// Fills in `total_in_preferred`, converting each native total into
+    /// `currency` with `converter`. Left unset if any of them, or
+    /// `currency` itself, cannot be converted.
+    pub fn with_preferred_currency(
+        mut self,
+        converter: &dyn CurrencyConverter,
+        currency: &str,
+    ) -> Self {
+        let base: Option<i64> = self
+            .native_totals
+            .iter()
+            .map(|total| converter.to_base(total.amount, &total.currency))
+            .sum();
+        self.total_in_preferred =
+            base.and_then(|base| converter.base_to(base, currency))
+                .map(|amount| Money {
+                    amount,
+                    currency: currency.to_string(),
+                });
+        self
+    }
+}
+
+fn serialize_minutes<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_minutes())
+}
+
+/// Sums leg fares for `passengers` travellers per currency, sorted by
+/// currency code.
+pub fn totals_by_currency(legs: &[LegInfo], passengers: u32) -> Vec<Money> {
+    let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+    for leg in legs {
+        *totals.entry(leg.currency.clone()).or_default() +=
+            leg.cost.saturating_mul(passengers as i64);
+    }
+    totals
+        .into_iter()
+        .map(|(currency, amount)| Money { amount, currency })
+        .collect()
+}
+
+/// Collapses itineraries that fly the same physical flights into one,
+/// listing each fare combination under `fare_options`. The first (best
+/// ranked) itinerary of each routing is kept, so input order is preserved.
+pub fn group_fare_options(itineraries: Vec<Itinerary>) -> Vec<Itinerary> {
+    let mut grouped: Vec<Itinerary> = vec![];
+    for itinerary in itineraries {
+        let routing = itinerary.routing();
+        match grouped.iter_mut().find(|x| x.routing() == routing) {
+            Some(existing) => existing.fare_options.extend(itinerary.fare_options),
+            None => grouped.push(itinerary),
+        }
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::currency::StaticRates;
+    use crate::flight::FlightDTO;
+    use crate::search::{Search, SearchQuery};
+    use crate::testutil::{flight, graph, query};
+
+    fn operated(flight: FlightDTO, airline: &str, cabin: Cabin) -> FlightDTO {
+        FlightDTO {
+            airline: airline.to_string(),
+            flight_number: "100".to_string(),
+            cabin,
+            ..flight
+        }
+    }
+
+    fn grouped(flights: Vec<FlightDTO>) -> Vec<Itinerary> {
+        let found = Search::new(graph(2, flights))
+            .find(query(0, 1, 1, 5))
+            .unwrap();
+        group_fare_options(found.iter().map(|x| x.to_itinerary()).collect())
+    }
+
+    #[test]
+    fn emissions_total_covers_every_passenger() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+            ],
+        ));
+        let query = SearchQuery {
+            passengers: 3,
+            ..query(0, 2, 3, 1)
+        };
+        let itinerary = search.find(query).unwrap()[0]
+            .to_itinerary()
+            .with_emissions(0.1);
+        // 100 distance units a leg, two legs, three passengers.
+        assert_eq!(itinerary.legs[0].estimated_emissions_kg, Some(10.0));
+        assert_eq!(itinerary.estimated_emissions_kg, Some(60.0));
+    }
+
+    #[test]
+    fn groups_cabins_of_the_same_flight() {
+        let itineraries = grouped(vec![
+            operated(flight(0, 0, 1, 100, "08:00", "09:00"), "AA", Cabin::Economy),
+            operated(
+                flight(1, 0, 1, 300, "08:00", "09:00"),
+                "AA",
+                Cabin::Business,
+            ),
+        ]);
+        assert_eq!(itineraries.len(), 1);
+        assert_eq!(itineraries[0].fare_options.len(), 2);
+    }
+
+    #[test]
+    fn keeps_other_airlines_at_the_same_times_apart() {
+        let itineraries = grouped(vec![
+            operated(flight(0, 0, 1, 100, "08:00", "09:00"), "AA", Cabin::Economy),
+            operated(flight(1, 0, 1, 120, "08:00", "09:00"), "DL", Cabin::Economy),
+        ]);
+        let airlines: Vec<&str> = itineraries
+            .iter()
+            .map(|x| x.legs[0].airline.as_str())
+            .collect();
+        assert_eq!(airlines, vec!["AA", "DL"]);
+        assert!(itineraries.iter().all(|x| x.fare_options.len() == 1));
+    }
+
+    fn money(amount: i64, currency: &str) -> Money {
+        Money {
+            amount,
+            currency: currency.to_string(),
+        }
+    }
+
+    #[test]
+    fn reports_native_fares_and_a_converted_total() {
+        let airports = graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                FlightDTO {
+                    currency: "EUR".to_string(),
+                    ..flight(1, 1, 2, 100, "10:00", "11:00")
+                },
+            ],
+        );
+        let rates = StaticRates::new("USD")
+            .with_rate("EUR", 1.1)
+            .with_rate("GBP", 1.25);
+        let search = Search::new(airports)
+            .with_currency_converter(Arc::new(rates))
+            .with_preferred_currency("GBP");
+
+        let itinerary = search
+            .find_itineraries(query(0, 2, 3, 1))
+            .unwrap()
+            .remove(0);
+        let fares: Vec<_> = itinerary
+            .legs
+            .iter()
+            .map(|x| money(x.cost, &x.currency))
+            .collect();
+        assert_eq!(fares, vec![money(100, "USD"), money(100, "EUR")]);
+        assert_eq!(
+            itinerary.native_totals,
+            vec![money(100, "EUR"), money(100, "USD")]
+        );
+        // 100 USD and 110 USD worth of euros, at 1.25 USD a pound.
+        assert_eq!(itinerary.total_in_preferred, Some(money(168, "GBP")));
+    }
+
+    #[test]
+    fn no_converted_total_in_a_currency_without_a_rate() {
+        let rates = StaticRates::new("USD").with_rate("EUR", 1.1);
+        let search = Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")]))
+            .with_currency_converter(Arc::new(rates))
+            .with_preferred_currency("JPY");
+        let itinerary = search
+            .find_itineraries(query(0, 1, 1, 1))
+            .unwrap()
+            .remove(0);
+        assert_eq!(itinerary.native_totals, vec![money(100, "USD")]);
+        assert_eq!(itinerary.total_in_preferred, None);
+    }
+
+    #[test]
+    fn legs_report_seats_left_in_every_cabin() {
+        // AA100 at 08:00 with `seats` left in `cabin`.
+        let sold = |id, cabin, seats| FlightDTO {
+            seats,
+            ..operated(flight(id, 0, 1, 100, "08:00", "09:00"), "AA", cabin)
+        };
+        let flights = vec![sold(0, Cabin::Economy, 5), sold(1, Cabin::Business, 2)];
+        let found = Search::new(graph(2, flights))
+            .find(query(0, 1, 1, 1))
+            .unwrap();
+        let leg = &found[0].to_itinerary().legs[0];
+        assert_eq!(leg.cabin, Cabin::Economy);
+        assert_eq!(
+            leg.seats_by_cabin,
+            HashMap::from([(Cabin::Economy, 5), (Cabin::Business, 2)])
+        );
+    }
+
+    #[test]
+    fn serializes_totals_and_legs() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 150, "10:00", "11:30"),
+            ],
+        ));
+        let itinerary = search.find(query(0, 2, 3, 1)).unwrap()[0].to_itinerary();
+        let json = serde_json::to_value(&itinerary).unwrap();
+        assert_eq!(json["total_cost"], 250);
+        assert_eq!(json["total_duration"], 210);
+        assert_eq!(json["num_stops"], 1);
+        assert_eq!(json["legs"][1]["from_name"], "A1");
+        assert_eq!(json["legs"][1]["arrive_at"], "2024-01-14T11:30:00");
+    }
+}
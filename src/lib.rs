@@ -0,0 +1,13 @@
+pub mod airport;
+pub mod currency;
+pub mod export;
+pub mod filter;
+pub mod flight;
+pub mod import;
+pub mod itinerary;
+pub mod mods;
+pub mod search;
+pub mod server;
+
+#[cfg(test)]
+mod testutil;
@@ -1,14 +1,73 @@
+use clap::Parser;
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::time::Duration;
 use sysinfo::System;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod airport;
-use airport::AirportsContainer;
-mod flight;
-mod search;
-use search::{Search, SearchQuery};
-mod import;
-use import::{CsvFlightImporter, FlightImporter};
+use rust_test::airport::AirportsContainer;
+use rust_test::import::{CsvFlightImporter, FlightImporter};
+use rust_test::search::{QueryError, Search, SearchError, SearchQuery};
+use rust_test::server;
+
+// Where `--serve` listens unless given an address.
+const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:3000";
+// Searches run at once unless `--max-concurrent-searches` says otherwise.
+const DEFAULT_MAX_CONCURRENT_SEARCHES: &str = "32";
+
+/// Finds flight itineraries between two airports, or serves the search
+/// over HTTP.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Origin airport id.
+    #[arg(long, required_unless_present = "serve")]
+    from: Option<usize>,
+    /// Destination airport id.
+    #[arg(long, required_unless_present = "serve")]
+    to: Option<usize>,
+    /// Departure date, as YYYY-MM-DD.
+    #[arg(long, required_unless_present = "serve")]
+    date: Option<String>,
+    /// Most times the search continues onward from any one airport.
+    #[arg(long, default_value_t = 3)]
+    hops: usize,
+    /// Number of itineraries to print.
+    #[arg(long, default_value_t = 10)]
+    results: usize,
+    /// Flights CSV to load.
+    #[arg(long, default_value = "data/flights.csv")]
+    data: String,
+    /// Serve the HTTP API, on ADDR if given, instead of running one search.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        default_missing_value = DEFAULT_SERVE_ADDR
+    )]
+    serve: Option<String>,
+    /// Most searches to run at once; further ones queue.
+    #[arg(long, value_name = "N", default_value = DEFAULT_MAX_CONCURRENT_SEARCHES)]
+    max_concurrent_searches: NonZeroUsize,
+    /// Fail searches that queue for longer than this many milliseconds, with
+    /// 503 when serving. Queued searches wait indefinitely otherwise.
+    #[arg(long, value_name = "MS")]
+    queue_timeout_ms: Option<u64>,
+}
+
+impl Args {
+    // The one-shot search asked for. Clap has already made sure `from`,
+    // `to` and `date` are given unless serving.
+    fn query(&self) -> Result<SearchQuery, QueryError> {
+        SearchQuery::builder()
+            .from(self.from.unwrap_or_default())
+            .to(self.to.unwrap_or_default())
+            .date(self.date.as_deref().unwrap_or_default())
+            .hops(self.hops)
+            .results(self.results)
+            .build()
+    }
+}
 
 #[tokio::main]
 async fn main() {
@@ -17,57 +76,163 @@ async fn main() {
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "rust_test=debug".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        // Logs go to stderr so stdout only carries the search results.
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .init();
+    let args = Args::parse();
+    let query = match &args.serve {
+        Some(_) => None,
+        None => match args.query() {
+            Ok(query) => Some(query),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(2)
+            }
+        },
+    };
     let mut sys = System::new_all();
     sys.refresh_all();
 
     let airports = AirportsContainer::new();
 
     // Try to import flights from CSV if the file exists
-    let flights_file = "data/flights.csv";
+    let flights_file = args.data.as_str();
     if Path::new(flights_file).exists() {
         let importer = CsvFlightImporter::new(&airports);
-        match importer.import_flights(flights_file) {
-            Ok(count) => println!("Imported {} flights from CSV", count),
+        match importer.import_with_report(flights_file) {
+            Ok(report) => tracing::info!(
+                count = report.imported,
+                skipped = report.skipped,
+                "imported flights from CSV"
+            ),
             Err(e) => {
-                println!("Failed to import flights from CSV: {}", e);
+                tracing::error!(error = %e, "failed to import flights from CSV");
             }
         }
     } else {
-        eprintln!("Flights file not found: {}", flights_file);
+        tracing::error!(path = flights_file, "flights file not found");
         std::process::exit(1)
     }
+    let stats = airports.stats();
+    tracing::info!(
+        airports = stats.num_airports,
+        flights = stats.num_flights,
+        earliest = ?stats.earliest_departure,
+        latest = ?stats.latest_departure,
+        avg_out_degree = stats.avg_out_degree,
+        "loaded graph"
+    );
 
-    let search = Search::new(airports);
+    let mut search = Search::new(airports).with_concurrency(args.max_concurrent_searches);
+    if let Some(timeout) = args.queue_timeout_ms {
+        search = search.with_queue_timeout(Duration::from_millis(timeout));
+    }
 
-    let query = SearchQuery {
-        from: 14576,
-        to: 14689,
-        date: "2024-01-14".to_string(),
-        hops: 3,
-        results: 10,
+    let Some(query) = query else {
+        let addr = args.serve.as_deref().unwrap_or(DEFAULT_SERVE_ADDR);
+        if let Err(e) = server::serve(search, addr).await {
+            tracing::error!(error = %e, "server failed");
+            std::process::exit(1)
+        }
+        return;
     };
-    println!(
-        "Searching for flights from {} to {} on {}",
-        query.from, query.to, query.date
+    tracing::info!(
+        from = query.from,
+        to = query.to,
+        date = %query.date,
+        "searching for flights"
     );
 
     let start = std::time::Instant::now();
     // Run the search asynchronously
-    let results = search.find_async(query).await;
+    let results = match search.find_async(query).await {
+        Ok(results) => results,
+        Err(SearchError::NoRouteFound) => vec![],
+        Err(e) => {
+            tracing::error!(error = %e, "search failed");
+            std::process::exit(1)
+        }
+    };
 
-    println!("Search completed in: {:?}", start.elapsed());
+    tracing::info!(elapsed = ?start.elapsed(), "search completed");
 
     for result in results {
         println!("---- {:#?}", result.readable_path());
     }
-    
+
     let pid = sysinfo::get_current_pid().expect("Failed to get current PID");
 
     // Lookup the process using the PID.
     if let Some(process) = sys.process(pid) {
         // process.memory() returns the memory usage in bytes.
-        println!("Memory usage: {} Mb", process.memory() / 1024 / 1024);
+        tracing::info!(megabytes = process.memory() / 1024 / 1024, "memory usage");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::error::ErrorKind;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("rust-test").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn args_build_the_search_query() {
+        let args = parse(&[
+            "--from",
+            "14576",
+            "--to",
+            "14689",
+            "--date",
+            "2024-01-14",
+            "--hops",
+            "2",
+        ])
+        .unwrap();
+        let query = args.query().unwrap();
+        assert_eq!((query.from, query.to), (14576, 14689));
+        assert_eq!(query.date, "2024-01-14");
+        assert_eq!((query.hops, query.results), (2, 10));
+        assert_eq!(args.data, "data/flights.csv");
+        assert_eq!(args.serve, None);
+    }
+
+    #[test]
+    fn missing_args_are_a_parse_error() {
+        let error = parse(&["--from", "14576", "--date", "2024-01-14"]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument);
+        assert!(error.to_string().contains("--to"), "{}", error);
+    }
+
+    #[test]
+    fn serving_needs_no_search() {
+        let args = parse(&["--serve"]).unwrap();
+        assert_eq!(args.serve.as_deref(), Some(DEFAULT_SERVE_ADDR));
+        let args = parse(&["--serve", "0.0.0.0:8080", "--data", "other.csv"]).unwrap();
+        assert_eq!(args.serve.as_deref(), Some("0.0.0.0:8080"));
+        assert_eq!(args.data, "other.csv");
+    }
+
+    #[test]
+    fn invalid_values_fail_the_query() {
+        let args = parse(&["--from", "1", "--to", "2", "--date", "2024-13-99"]).unwrap();
+        assert_eq!(
+            args.query().unwrap_err(),
+            QueryError::InvalidDate("2024-13-99".to_string())
+        );
+        let args = parse(&[
+            "--from",
+            "1",
+            "--to",
+            "2",
+            "--date",
+            "2024-01-14",
+            "--hops",
+            "0",
+        ]);
+        assert_eq!(args.unwrap().query().unwrap_err(), QueryError::ZeroHops);
     }
 }
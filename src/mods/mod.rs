@@ -1 +1 @@
-pub mod tpl;
\ No newline at end of file
+pub mod tpl;
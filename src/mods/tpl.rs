@@ -1,13 +1,92 @@
 use sailfish::Template;
 
+use crate::itinerary::Itinerary;
+
 #[derive(Template)]
 #[template(path = "hello.stpl", escape = false)]
 struct HelloTemplate {
     messages: Vec<String>,
     name: String,
 }
+
+#[derive(Template)]
+#[template(path = "results.stpl")]
+struct ResultsTemplate {
+    itineraries: Vec<ItineraryView>,
+}
+
+/// One flight of an `ItineraryView`, formatted for display.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LegView {
+    /// Designator such as "AA100", or the flight id when there is none.
+    pub flight: String,
+    pub from: String,
+    pub to: String,
+    pub depart_at: String,
+    pub arrive_at: String,
+    pub cost: i64,
+    pub currency: String,
+}
+
+/// An itinerary as the results page shows it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItineraryView {
+    pub legs: Vec<LegView>,
+    pub total_cost: i64,
+    /// Currency of the first leg; mixed-currency totals are not converted.
+    pub currency: String,
+    /// E.g. "5h 30m".
+    pub duration: String,
+    /// E.g. "nonstop" or "1 stop".
+    pub stops: String,
+}
+
+impl ItineraryView {
+    pub fn new(itinerary: &Itinerary) -> Self {
+        let legs = itinerary
+            .legs
+            .iter()
+            .map(|leg| {
+                let designator = format!("{}{}", leg.airline, leg.flight_number);
+                LegView {
+                    flight: if designator.is_empty() {
+                        leg.flight_id.to_string()
+                    } else {
+                        designator
+                    },
+                    from: leg.from_name.clone(),
+                    to: leg.to_name.clone(),
+                    depart_at: leg.depart_at.format("%Y-%m-%d %H:%M").to_string(),
+                    arrive_at: leg.arrive_at.format("%Y-%m-%d %H:%M").to_string(),
+                    cost: leg.cost,
+                    currency: leg.currency.clone(),
+                }
+            })
+            .collect();
+        let minutes = itinerary.total_duration.num_minutes();
+        ItineraryView {
+            legs,
+            total_cost: itinerary.total_cost,
+            currency: itinerary
+                .legs
+                .first()
+                .map(|leg| leg.currency.clone())
+                .unwrap_or_default(),
+            duration: format!("{}h {:02}m", minutes / 60, minutes % 60),
+            stops: match itinerary.num_stops {
+                0 => "nonstop".to_string(),
+                1 => "1 stop".to_string(),
+                n => format!("{} stops", n),
+            },
+        }
+    }
+}
+
 pub trait Tpl {
     fn render(&self, name: String) -> String;
+
+    /// An HTML page listing each itinerary's flights, costs and duration.
+    fn render_results(&self, itineraries: &[Itinerary]) -> String;
 }
 
 #[derive(Clone)]
@@ -23,4 +102,55 @@ impl Tpl for TplRenderer {
         };
         ctx.render().unwrap()
     }
+
+    fn render_results(&self, itineraries: &[Itinerary]) -> String {
+        let ctx = ResultsTemplate {
+            itineraries: itineraries.iter().map(ItineraryView::new).collect(),
+        };
+        ctx.render().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flight::FlightDTO;
+    use crate::search::Search;
+    use crate::testutil::{flight, flown_by, graph, query};
+
+    fn numbered(number: &str, flight: FlightDTO) -> FlightDTO {
+        FlightDTO {
+            flight_number: number.to_string(),
+            ..flight
+        }
+    }
+
+    #[test]
+    fn results_page_lists_every_itinerarys_flights() {
+        let search = Search::new(graph(
+            2,
+            vec![
+                numbered(
+                    "100",
+                    flown_by("AA", flight(0, 0, 1, 100, "08:00", "09:00")),
+                ),
+                numbered(
+                    "200",
+                    flown_by("BA", flight(1, 0, 1, 150, "10:00", "11:30")),
+                ),
+            ],
+        ));
+        let itineraries: Vec<_> = search
+            .find(query(0, 1, 1, 5))
+            .unwrap()
+            .iter()
+            .map(|state| search.itinerary(state))
+            .collect();
+        assert_eq!(itineraries.len(), 2);
+
+        let html = TplRenderer { messages: vec![] }.render_results(&itineraries);
+        assert!(html.contains("AA100"), "{}", html);
+        assert!(html.contains("BA200"), "{}", html);
+        assert!(html.contains("1h 30m"), "{}", html);
+    }
 }
@@ -1,211 +1,4350 @@
-use crate::airport::{Airport, AirportsContainer};
-use crate::flight::FlightEdge;
-use chrono::{Duration, NaiveDate, NaiveDateTime};
+use crate::airport::{Airport, AirportsContainer, SearchView, ViewFlight, MIN_CONNECTION_MINUTES};
+use crate::currency::CurrencyConverter;
+use crate::filter::{Constraint, FilterSet};
+use crate::flight::{Cabin, FlightEdge};
+use crate::itinerary::{group_fare_options, totals_by_currency, FareOption, Itinerary, LegInfo};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use lru::LruCache;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rayon::ThreadPool;
+use serde::{Deserialize, Serialize, Serializer};
 use std::cmp::{Ordering, Reverse};
-use std::collections::BinaryHeap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
 use tokio::task;
+use tokio::time;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
 
-#[derive(Clone)]
+// Blend used by `Objective::BestValue`; price and duration are each
+// normalized to the candidate set before weighting.
+const BEST_VALUE_PRICE_WEIGHT: f64 = 0.6;
+const BEST_VALUE_DURATION_WEIGHT: f64 = 0.4;
+// How many candidates to collect per requested result for objectives that
+// have to see the whole candidate set before ranking.
+const CANDIDATE_MULTIPLIER: usize = 5;
+// Rank added per leg under `Objective::Hops`; large enough that cost only
+// breaks ties between itineraries with the same number of legs.
+const HOP_RANK: i64 = 1 << 32;
+/// Default for `SearchQuery::max_trip_hours`.
+pub const DEFAULT_MAX_TRIP_HOURS: u32 = 48;
+/// Default for `SearchQuery::search_window_hours`.
+pub const DEFAULT_SEARCH_WINDOW_HOURS: u32 = 24;
+/// Default for `SearchQuery::date_flex_penalty`, in cost units per day.
+pub const DEFAULT_DATE_FLEX_PENALTY: i32 = 50;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Objective {
+    /// Cheapest first.
+    #[default]
+    Cost,
+    /// "Recommended" ordering: a normalized blend of price and duration.
+    BestValue,
+    /// Deliberate stopovers: the longest single connection first, then
+    /// cheapest. Connections are still bound by the search window.
+    MaxLayover,
+    /// Most likely to complete as planned given the search's misconnect
+    /// rates, then cheapest.
+    Reliability,
+    /// Earliest arrival at the destination first. The search itself is
+    /// ordered by arrival time instead of price.
+    EarliestArrival,
+    /// Lowest score under the query's `preferences` first.
+    Personalized,
+    /// Shortest time from first departure to last arrival first, layovers
+    /// included. The search itself is ordered by elapsed time.
+    Duration,
+    /// Fewest legs first, then cheapest.
+    Hops,
+}
+
+impl Objective {
+    // Objectives that rank on the full candidate set rather than the heap order.
+    fn needs_candidates(&self) -> bool {
+        matches!(
+            self,
+            Objective::BestValue
+                | Objective::MaxLayover
+                | Objective::Reliability
+                | Objective::Personalized
+        )
+    }
+}
+
+/// Weights for `Objective::Personalized`. Each criterion is scaled to
+/// 0.0 (best) ..= 1.0 (worst) before weighting, and candidates are ranked
+/// by the weighted sum, lowest first:
+///
+/// - price and duration: min-max normalized over the candidate set, so the
+///   cheapest (fastest) candidate scores 0 and the dearest (slowest) 1;
+/// - stops: divided by the most stops of any candidate;
+/// - carrier affinity: the share of legs not flown by `preferred_airlines`;
+/// - reliability: the chance of misconnecting, `1 - completion_probability`.
+///
+/// Only the ratios between weights matter. The default weighs price alone.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    pub price: f64,
+    pub duration: f64,
+    pub stops: f64,
+    pub carrier_affinity: f64,
+    pub preferred_airlines: Vec<String>,
+    pub reliability: f64,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            price: 1.0,
+            duration: 0.0,
+            stops: 0.0,
+            carrier_affinity: 0.0,
+            preferred_airlines: vec![],
+            reliability: 0.0,
+        }
+    }
+}
+
+// Where `value` falls between the smallest (0.0) and largest (1.0) of
+// `values`.
+fn normalize(values: &[f64], value: f64) -> f64 {
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max > min {
+        (value - min) / (max - min)
+    } else {
+        0.0
+    }
+}
+
+/// How first legs enter the search frontier.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum SeedingStrategy {
+    /// Every first leg in the window is pushed up front.
+    #[default]
+    All,
+    /// First legs are released one at a time in the order the objective
+    /// ranks them (cost, or arrival for `Objective::EarliestArrival`), and
+    /// only once they could beat the best state on the frontier. Results
+    /// are the same as with `All`, but legs that could never make the cut
+    /// are not generated.
+    Ordered,
+}
+
+/// Work done by a search.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    /// States pushed onto the frontier, first legs included.
+    pub generated: usize,
+    /// States popped off the frontier and examined.
+    pub expanded: usize,
+    /// Most states on the frontier at once.
+    pub heap_peak: usize,
+    /// States never pushed because enough cheaper, earlier paths to the
+    /// same airport had already been continued.
+    pub dominated: usize,
+    /// Time spent traversing, excluding seeding and ranking.
+    pub elapsed: std::time::Duration,
+}
+
+impl SearchStats {
+    // Totals for two traversals run one after the other.
+    fn merge(&mut self, other: SearchStats) {
+        self.generated += other.generated;
+        self.expanded += other.expanded;
+        self.heap_peak = self.heap_peak.max(other.heap_peak);
+        self.dominated += other.dominated;
+        self.elapsed += other.elapsed;
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct SearchQuery {
     pub from: usize,
     pub to: usize,
     pub date: String,
+    /// Most times the search continues onward from any one airport, as in
+    /// a k-shortest-paths search. Not otherwise a limit on legs; see
+    /// `Constraint::MaxStops` for that. 1 searches nonstop flights only.
     pub hops: usize,
     pub results: usize,
+    pub objective: Objective,
+    /// Airports to search from instead of `from`, e.g. every airport
+    /// serving a city. Results are the best from any of them. Takes
+    /// precedence over `from_code` and `from_name`.
+    pub origins: Option<Vec<usize>>,
+    /// Airport code such as "JFK" to search from instead of `from`. Takes
+    /// precedence over `from_name`.
+    pub from_code: Option<String>,
+    /// Airports any of which may end the itinerary, instead of `to`, e.g.
+    /// alternate airports for a city. Takes precedence over `to_code` and
+    /// `to_name`.
+    pub destinations: Option<Vec<usize>>,
+    /// Airport code to search to instead of `to`. Takes precedence over
+    /// `to_name`.
+    pub to_code: Option<String>,
+    /// Airport name to search from instead of `from`; every airport matching
+    /// it is used as a possible origin.
+    pub from_name: Option<String>,
+    /// Airport name to search to instead of `to`; any matching airport is
+    /// accepted as the destination.
+    pub to_name: Option<String>,
+    /// Hard constraints exclude itineraries; soft ones only penalize them.
+    pub filters: FilterSet,
+    /// Hard deadline for arriving at the destination, in local time where
+    /// each leg lands. Only ever tightens the search's arrival horizon.
+    pub arrive_before: Option<NaiveDateTime>,
+    /// Number of states the search may expand before degrading. Past the
+    /// budget, the per-airport expansion cap (`hops`) is halved each time
+    /// another budget's worth of states is expanded. The search stops when
+    /// the cap reaches zero. This bounds the work at roughly
+    /// `budget * (log2(hops) + 1)` expansions. The cost is that later, more
+    /// expensive alternatives may be missed. A degraded search reports it
+    /// through `SearchResultSet::degraded`.
+    pub expansion_budget: Option<usize>,
+    /// Also search this many days either side of `date`.
+    pub date_flex_days: u32,
+    /// Ranking penalty per day an itinerary departs away from `date`, so
+    /// the requested day wins unless another is substantially cheaper.
+    /// Defaults to `DEFAULT_DATE_FLEX_PENALTY`.
+    pub date_flex_penalty: Option<i32>,
+    /// Most times the itinerary may switch airline between consecutive
+    /// legs. A single-carrier itinerary has zero changes.
+    pub max_carrier_changes: Option<usize>,
+    /// Most different airlines the itinerary may fly, however often it
+    /// switches between them.
+    pub max_airlines: Option<usize>,
+    /// Airports every itinerary must pass through, in any order. The
+    /// origin and destination count as passed through.
+    pub via: Option<Vec<usize>>,
+    /// Only fly these airlines, e.g. to stay within one alliance. Same as a
+    /// hard `Constraint::Airlines` filter.
+    pub include_airlines: Option<Vec<String>>,
+    /// Never fly these airlines. Same as a hard
+    /// `Constraint::AvoidAirlines` filter.
+    pub exclude_airlines: Option<Vec<String>>,
+    /// Flight ids never to fly, e.g. cancelled flights.
+    #[serde(serialize_with = "serialize_sorted")]
+    pub exclude_flights: Option<HashSet<usize>>,
+    /// Airports never to depart from or land at, e.g. ones closed by
+    /// weather.
+    #[serde(serialize_with = "serialize_sorted")]
+    pub exclude_airports: Option<HashSet<usize>>,
+    /// Fly every leg in this cabin. Any cabin when unset.
+    pub cabin: Option<Cabin>,
+    /// Most an itinerary may cost per passenger, in the base currency when
+    /// the search has a currency converter. Paths are dropped as soon as
+    /// they go over it, so a tight budget also speeds up the search.
+    pub max_cost: Option<i64>,
+    /// Keep itineraries with the same flights as a better-ranked one, e.g.
+    /// a flight departing at midnight found from both flexible days.
+    pub allow_duplicates: bool,
+    pub seeding: SeedingStrategy,
+    /// Travellers booking together. Every leg needs a seat for each of
+    /// them, and result totals cover the whole party. Zero counts as one.
+    pub passengers: u32,
+    /// Shuffles results the ranking considers equal, reproducibly for a
+    /// given seed. Without it ties keep the search's own order.
+    pub tie_break_seed: Option<u64>,
+    /// Weights used by `Objective::Personalized`.
+    pub preferences: Preferences,
+    /// Of the origins matched by `from_name`, only start from this one.
+    /// Nothing is found if it is not among them.
+    pub force_first: Option<usize>,
+    /// Of the destinations matched by `to_name`, only accept this one.
+    /// Nothing is found if it is not among them.
+    pub force_last: Option<usize>,
+    /// Earliest first-leg departure, as "HH:MM" on `date`. Connections are
+    /// not affected.
+    pub depart_after: Option<String>,
+    /// Latest first-leg departure, as "HH:MM" on `date`.
+    pub depart_before: Option<String>,
+    /// Shortest allowed connection. Defaults to `MIN_CONNECTION_MINUTES`.
+    pub min_layover_minutes: Option<u32>,
+    /// Longest allowed connection. Unbounded by default, apart from the
+    /// search window.
+    pub max_layover_minutes: Option<u32>,
+    /// Every leg must land within this many hours of the start of `date`,
+    /// at the origin. Defaults to `DEFAULT_MAX_TRIP_HOURS`.
+    pub max_trip_hours: Option<u32>,
+    /// Connections must depart within this many hours of the start of
+    /// `date`, at the origin. Defaults to `DEFAULT_SEARCH_WINDOW_HOURS`.
+    pub search_window_hours: Option<u32>,
+}
+
+impl SearchQuery {
+    pub fn builder() -> SearchQueryBuilder {
+        SearchQueryBuilder::default()
+    }
+
+    // Whether only nonstop itineraries can be results, so the search can
+    // skip the frontier.
+    fn nonstop_only(&self) -> bool {
+        self.hops == 1 || self.filters.max_stops() == Some(0)
+    }
+
+    // Ranking penalty for departing `offset` days away from `date`.
+    fn flex_penalty(&self, offset: i64) -> i32 {
+        let days = i32::try_from(offset.unsigned_abs()).unwrap_or(i32::MAX);
+        self.date_flex_penalty
+            .unwrap_or(DEFAULT_DATE_FLEX_PENALTY)
+            .saturating_mul(days)
+    }
+}
+
+// Writes an id set in ascending order, so equal queries serialize alike and
+// share a result cache entry.
+fn serialize_sorted<S: Serializer>(
+    set: &Option<HashSet<usize>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    set.as_ref()
+        .map(|x| x.iter().collect::<BTreeSet<_>>())
+        .serialize(serializer)
+}
+
+/// Why a `SearchQueryBuilder` refused to build a query.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    /// `hops` was zero, so the search could not leave the origin.
+    ZeroHops,
+    /// `results` was zero.
+    ZeroResults,
+    /// `date` was not a "YYYY-MM-DD" date.
+    InvalidDate(String),
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::ZeroHops => write!(f, "hops must be at least 1"),
+            QueryError::ZeroResults => write!(f, "results must be at least 1"),
+            QueryError::InvalidDate(date) => {
+                write!(f, "invalid date {:?}, expected YYYY-MM-DD", date)
+            }
+        }
+    }
+}
+
+impl Error for QueryError {}
+
+/// Why `Search::find` returned no itineraries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchError {
+    /// The query itself was malformed.
+    InvalidQuery(QueryError),
+    /// No airport matches the query's origin.
+    UnknownOrigin,
+    /// No airport matches the query's destination.
+    UnknownDestination,
+    /// Both airports exist, but no itinerary between them satisfies the
+    /// query.
+    NoRouteFound,
+    /// `find_async` waited longer than the search's queue timeout for one
+    /// of its `with_concurrency` permits.
+    Overloaded,
+    /// `find_async` lost the search it ran in the background, e.g. because
+    /// it panicked.
+    Internal,
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::InvalidQuery(e) => e.fmt(f),
+            SearchError::UnknownOrigin => write!(f, "unknown origin airport"),
+            SearchError::UnknownDestination => write!(f, "unknown destination airport"),
+            SearchError::NoRouteFound => write!(f, "no route found"),
+            SearchError::Overloaded => write!(f, "too many searches in progress"),
+            SearchError::Internal => write!(f, "search failed unexpectedly"),
+        }
+    }
+}
+
+impl Error for SearchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SearchError::InvalidQuery(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<QueryError> for SearchError {
+    fn from(e: QueryError) -> Self {
+        SearchError::InvalidQuery(e)
+    }
+}
+
+// Midnight at the start of a "YYYY-MM-DD" date.
+fn start_of_day(date: &str) -> Result<NaiveDateTime, QueryError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|x| x.and_hms_opt(0, 0, 0).unwrap())
+        .map_err(|_| QueryError::InvalidDate(date.to_string()))
+}
+
+/// Builds a `SearchQuery`, checking it once in `build` rather than leaving
+/// a bad date or a zero limit to surface in the middle of a search. Fields
+/// not set keep their `SearchQuery::default()` values.
+#[derive(Clone, Debug, Default)]
+pub struct SearchQueryBuilder {
+    query: SearchQuery,
+}
+
+impl SearchQueryBuilder {
+    pub fn from(mut self, from: usize) -> Self {
+        self.query.from = from;
+        self
+    }
+
+    pub fn to(mut self, to: usize) -> Self {
+        self.query.to = to;
+        self
+    }
+
+    /// Departure day as "YYYY-MM-DD".
+    pub fn date(mut self, date: &str) -> Self {
+        self.query.date = date.to_string();
+        self
+    }
+
+    pub fn hops(mut self, hops: usize) -> Self {
+        self.query.hops = hops;
+        self
+    }
+
+    pub fn results(mut self, results: usize) -> Self {
+        self.query.results = results;
+        self
+    }
+
+    pub fn objective(mut self, objective: Objective) -> Self {
+        self.query.objective = objective;
+        self
+    }
+
+    pub fn from_code(mut self, code: &str) -> Self {
+        self.query.from_code = Some(code.to_string());
+        self
+    }
+
+    pub fn to_code(mut self, code: &str) -> Self {
+        self.query.to_code = Some(code.to_string());
+        self
+    }
+
+    pub fn filters(mut self, filters: FilterSet) -> Self {
+        self.query.filters = filters;
+        self
+    }
+
+    pub fn arrive_before(mut self, deadline: NaiveDateTime) -> Self {
+        self.query.arrive_before = Some(deadline);
+        self
+    }
+
+    pub fn max_cost(mut self, max_cost: i64) -> Self {
+        self.query.max_cost = Some(max_cost);
+        self
+    }
+
+    pub fn max_carrier_changes(mut self, changes: usize) -> Self {
+        self.query.max_carrier_changes = Some(changes);
+        self
+    }
+
+    pub fn max_airlines(mut self, airlines: usize) -> Self {
+        self.query.max_airlines = Some(airlines);
+        self
+    }
+
+    pub fn via(mut self, airports: Vec<usize>) -> Self {
+        self.query.via = Some(airports);
+        self
+    }
+
+    pub fn include_airlines(mut self, airlines: Vec<String>) -> Self {
+        self.query.include_airlines = Some(airlines);
+        self
+    }
+
+    pub fn exclude_airlines(mut self, airlines: Vec<String>) -> Self {
+        self.query.exclude_airlines = Some(airlines);
+        self
+    }
+
+    pub fn exclude_flights(mut self, flights: HashSet<usize>) -> Self {
+        self.query.exclude_flights = Some(flights);
+        self
+    }
+
+    pub fn exclude_airports(mut self, airports: HashSet<usize>) -> Self {
+        self.query.exclude_airports = Some(airports);
+        self
+    }
+
+    pub fn cabin(mut self, cabin: Cabin) -> Self {
+        self.query.cabin = Some(cabin);
+        self
+    }
+
+    pub fn max_trip_hours(mut self, hours: u32) -> Self {
+        self.query.max_trip_hours = Some(hours);
+        self
+    }
+
+    pub fn search_window_hours(mut self, hours: u32) -> Self {
+        self.query.search_window_hours = Some(hours);
+        self
+    }
+
+    pub fn passengers(mut self, passengers: u32) -> Self {
+        self.query.passengers = passengers;
+        self
+    }
+
+    pub fn date_flex_days(mut self, days: u32) -> Self {
+        self.query.date_flex_days = days;
+        self
+    }
+
+    pub fn date_flex_penalty(mut self, penalty: i32) -> Self {
+        self.query.date_flex_penalty = Some(penalty);
+        self
+    }
+
+    pub fn build(self) -> Result<SearchQuery, QueryError> {
+        if self.query.hops == 0 {
+            return Err(QueryError::ZeroHops);
+        }
+        if self.query.results == 0 {
+            return Err(QueryError::ZeroResults);
+        }
+        start_of_day(&self.query.date)?;
+        Ok(self.query)
+    }
+}
+
+// Airports a traversal starts from and may end at.
+struct Endpoints {
+    sources: HashSet<usize>,
+    targets: HashSet<usize>,
+}
+
+// What a traversal found, and whether it gave up on completeness to stay
+// within its expansion budget or was cancelled.
+#[derive(Default)]
+struct Traversal {
+    results: Vec<PathState>,
+    degraded: bool,
+    cancelled: bool,
+    stats: SearchStats,
+}
+
+// A leg reached by `Search::find_cheapest`, linked to the leg before it
+// instead of copying the whole path.
+struct Label {
+    flight: Arc<FlightEdge>,
+    // Airport `flight` lands at.
+    at: usize,
+    // Of the path ending with `flight`, in the base currency.
+    cost: i64,
+    origin_offset: Duration,
+    // Flights on the path ending with `flight`.
+    legs: usize,
+    previous: Option<usize>,
+}
+
+/// Results of a search together with the wider candidate set they were
+/// picked from.
+#[derive(Clone, Debug)]
+pub struct SearchResultSet {
+    pub results: Vec<PathState>,
+    pub candidates: Vec<PathState>,
+    limit: usize,
+    /// The search ran out of its expansion budget, so results are the best
+    /// found so far rather than guaranteed best.
+    pub degraded: bool,
+    pub stats: SearchStats,
+}
+
+impl SearchResultSet {
+    /// Re-filters the cached candidates without searching again. Hard
+    /// constraints drop candidates; soft ones push them down the ranking.
+    pub fn refine(&self, filters: &FilterSet) -> Vec<PathState> {
+        let mut refined: Vec<(i32, PathState)> = self
+            .candidates
+            .iter()
+            .filter(|x| filters.admits(x))
+            .map(|x| (filters.penalty(x), x.clone()))
+            .collect();
+        // Stable, so candidates that pick up no penalty keep the search's
+        // own ranking.
+        refined.sort_by_key(|(penalty, _)| *penalty);
+        refined
+            .into_iter()
+            .map(|(_, x)| x)
+            .take(self.limit)
+            .collect()
+    }
+}
+
+// Results of recent `find` calls, keyed by the graph's data version and
+// the query as JSON.
+type ResultCache = Mutex<LruCache<(u64, String), Vec<PathState>>>;
+
+type Sources = Vec<Arc<RwLock<Airport>>>;
+
+type SharedView = Mutex<Arc<SearchView>>;
+
+// Cost, arrival (UTC), origin offset and visited airports of a partial
+// path the search continued onward from.
+type Reached = (i64, NaiveDateTime, Duration, HashSet<usize>);
+
+// A cached search as written by `Search::dump_cache`: the query and the
+// flight ids of each result, in ranked order.
+#[derive(Serialize, Deserialize)]
+struct CachedSearch {
+    query: SearchQuery,
+    itineraries: Vec<Vec<usize>>,
 }
 
+#[derive(Clone)]
 pub struct Search {
     pub airports: Arc<RwLock<AirportsContainer>>,
+    // Dedicated pool for `find_async`; when unset searches run on tokio's
+    // blocking pool.
+    pool: Option<Arc<ThreadPool>>,
+    // kg of CO₂ per passenger per unit of flight distance.
+    emission_factor: Option<f64>,
+    // Probability of missing the connection from the first flight id to the
+    // second; pairs not listed never misconnect.
+    misconnect_rates: Arc<HashMap<(usize, usize), f64>>,
+    converter: Option<Arc<dyn CurrencyConverter>>,
+    // Currency structured results report their total in.
+    preferred_currency: Option<String>,
+    cache: Option<Arc<ResultCache>>,
+    // Build first legs on rayon threads.
+    parallel: bool,
+    // Lock-free copy of the graph to traverse; see `with_read_view`.
+    view: Option<Arc<SharedView>>,
+    // Caps the searches `find_async` runs at once.
+    permits: Option<Arc<Semaphore>>,
+    // How long `find_async` waits for a permit before giving up.
+    queue_timeout: Option<std::time::Duration>,
 }
 
+/// An itinerary found by a search. Only the search builds these, always
+/// from at least one flight, so a `PathState` never has an empty path.
 #[derive(Clone, Debug)]
 pub struct PathState {
-    cost: i32,
+    cost: i64,
+    // Ranking surcharge from violated soft constraints; never part of the
+    // price shown to the user.
+    penalty: i32,
+    // Product of the success rates of every connection so far.
+    completion_probability: f64,
+    // Decides what `rank` orders by.
+    objective: Objective,
+    // Party size; `cost` is per passenger.
+    passengers: u32,
     current: Arc<FlightEdge>,
+    // Id of the airport `current` lands at.
+    at: usize,
     path: Vec<Arc<FlightEdge>>,
+    // Ids of every airport on `path`, so cycles are caught without
+    // locking each airport.
+    visited: HashSet<usize>,
 }
 impl PathState {
+    // A path flying just `flight`, whose fare is `cost` in the search's
+    // base currency.
+    fn start(flight: &Arc<FlightEdge>, cost: i64) -> Self {
+        let at = flight.to.read().unwrap().id;
+        PathState {
+            cost,
+            penalty: 0,
+            completion_probability: 1.0,
+            objective: Objective::Cost,
+            passengers: 1,
+            current: Arc::clone(flight),
+            at,
+            path: vec![Arc::clone(flight)],
+            visited: HashSet::from([flight.from.read().unwrap().id, at]),
+        }
+    }
+
+    // The state after also flying `flight`, or None if its total cost
+    // would overflow.
+    fn extend(&self, flight: &Arc<FlightEdge>, cost: i64) -> Option<Self> {
+        self.extend_to(flight, flight.to.read().unwrap().id, cost)
+    }
+
+    // `extend` for a flight already known to land at airport `to`.
+    fn extend_to(&self, flight: &Arc<FlightEdge>, to: usize, cost: i64) -> Option<Self> {
+        let cost = self.cost.checked_add(cost)?;
+        let mut path = self.path.clone();
+        path.push(Arc::clone(flight));
+        let mut visited = self.visited.clone();
+        visited.insert(to);
+        Some(PathState {
+            cost,
+            penalty: self.penalty,
+            completion_probability: self.completion_probability,
+            objective: self.objective,
+            passengers: self.passengers,
+            current: Arc::clone(flight),
+            at: to,
+            path,
+            visited,
+        })
+    }
+
+    // Applies the query's constraints to the state, returning None if a
+    // hard one is violated.
+    fn filtered(mut self, query: &SearchQuery) -> Option<Self> {
+        let passengers = query.passengers.max(1);
+        if self.current.seats < passengers {
+            return None;
+        }
+        if query.max_cost.is_some_and(|max| self.cost > max) {
+            return None;
+        }
+        if query.cabin.is_some_and(|cabin| self.current.cabin != cabin) {
+            return None;
+        }
+        if let Some(max) = query.max_carrier_changes {
+            if self.carrier_changes() > max {
+                return None;
+            }
+        }
+        if let Some(max) = query.max_airlines {
+            if self.airline_count() > max {
+                return None;
+            }
+        }
+        // Earlier legs were checked when they were added.
+        let airline = &self.current.airline;
+        if let Some(include) = &query.include_airlines {
+            if !include.contains(airline) {
+                return None;
+            }
+        }
+        if let Some(exclude) = &query.exclude_airlines {
+            if exclude.contains(airline) {
+                return None;
+            }
+        }
+        if let Some(exclude) = &query.exclude_flights {
+            if exclude.contains(&self.current.flight_id) {
+                return None;
+            }
+        }
+        if let Some(exclude) = &query.exclude_airports {
+            if exclude.contains(&self.current.from.read().unwrap().id)
+                || exclude.contains(&self.current.to.read().unwrap().id)
+            {
+                return None;
+            }
+        }
+        if !query.filters.admits(&self) {
+            return None;
+        }
+        self.penalty = query.filters.penalty(&self);
+        self.objective = query.objective;
+        self.passengers = passengers;
+        Some(self)
+    }
+
+    // Number of consecutive leg pairs flown by different airlines.
+    fn carrier_changes(&self) -> usize {
+        self.path
+            .windows(2)
+            .filter(|pair| pair[0].airline != pair[1].airline)
+            .count()
+    }
+
+    // Whether the path touches every airport in `via`.
+    fn visits_all(&self, via: &Option<Vec<usize>>) -> bool {
+        via.as_ref()
+            .is_none_or(|via| via.iter().all(|x| self.visited.contains(x)))
+    }
+
+    // Number of distinct airlines flown.
+    fn airline_count(&self) -> usize {
+        self.path
+            .iter()
+            .map(|x| x.airline.as_str())
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    // Key the search is ordered by. It never decreases as a path is
+    // extended.
+    fn rank(&self) -> i64 {
+        let base = match self.objective {
+            Objective::EarliestArrival => self.current.arrive_utc.and_utc().timestamp() / 60,
+            Objective::Duration => self.total_duration().num_minutes(),
+            Objective::Hops => (self.path.len() as i64 * HOP_RANK).saturating_add(self.cost),
+            _ => self.cost,
+        };
+        base.saturating_add(self.penalty as i64)
+    }
+
     pub fn readable_path(&self) -> Vec<String> {
         let mut paths = self
             .path
             .iter()
             .map(|x| {
                 let edge = x.clone();
+                let designator = edge.designator();
+                let operated_as = match designator.is_empty() {
+                    true => String::new(),
+                    false => format!(" ({})", designator),
+                };
+                let from = edge.from.read().unwrap();
+                let to = edge.to.read().unwrap();
                 format!(
-                    "Flight {} from {} to {}, from {} to {}",
+                    "Flight {}{} from {} to {}, from {} to {}",
                     edge.flight_id,
-                    edge.from.read().unwrap().name,
-                    edge.to.read().unwrap().name,
-                    edge.depart_at,
-                    edge.arrive_at
+                    operated_as,
+                    from.name,
+                    to.name,
+                    from.format_local(edge.depart_at),
+                    to.format_local(edge.arrive_at)
                 )
             })
             .collect::<Vec<String>>();
-        paths.push(format!("Total cost: {}", self.cost));
+        paths.push(format!(
+            "Total cost: {}",
+            self.cost.saturating_mul(self.passengers as i64)
+        ));
+        let duration = self.total_duration();
+        paths.push(format!(
+            "Total duration: {}h {}m",
+            duration.num_hours(),
+            duration.num_minutes() % 60
+        ));
         paths
     }
+
+    /// Calendar day the itinerary's first flight departs.
+    pub fn departure_date(&self) -> NaiveDate {
+        self.first_leg().depart_at.date()
+    }
+
+    // The flight the itinerary starts with. Falls back to `current`, also a
+    // leg of the path, rather than panic should the path ever be empty.
+    fn first_leg(&self) -> &Arc<FlightEdge> {
+        self.path.first().unwrap_or(&self.current)
+    }
+
+    /// The flights of the itinerary, in order.
+    pub fn legs(&self) -> &[Arc<FlightEdge>] {
+        &self.path
+    }
+
+    /// Price per passenger, in the base currency when the search has a
+    /// currency converter.
+    pub fn cost(&self) -> i64 {
+        self.cost
+    }
+
+    /// Chance of flying every leg as planned, i.e. without missing any
+    /// connection.
+    pub fn completion_probability(&self) -> f64 {
+        self.completion_probability
+    }
+
+    pub fn to_itinerary(&self) -> Itinerary {
+        let legs: Vec<LegInfo> = self
+            .path
+            .iter()
+            .map(|edge| {
+                let from = edge.from.read().unwrap();
+                let to = edge.to.read().unwrap();
+                // Other cabins of the same flight are separate edges in the
+                // same departure bucket.
+                let seats_by_cabin = from
+                    .outgoing
+                    .get(&edge.depart_at)
+                    .into_iter()
+                    .flat_map(|heap| heap.iter().map(|x| x.flight()))
+                    .filter(|x| Arc::ptr_eq(&x.to, &edge.to) && x.arrive_at == edge.arrive_at)
+                    .map(|x| (x.cabin, x.seats))
+                    .collect();
+                LegInfo {
+                    flight_id: edge.flight_id,
+                    from_id: from.id,
+                    from_name: from.name.clone(),
+                    to_id: to.id,
+                    to_name: to.name.clone(),
+                    airline: edge.airline.clone(),
+                    flight_number: edge.flight_number.clone(),
+                    depart_at: edge.depart_at,
+                    arrive_at: edge.arrive_at,
+                    cost: edge.cost,
+                    currency: edge.currency.clone(),
+                    distance: edge.distance,
+                    cabin: edge.cabin,
+                    seats_by_cabin,
+                    estimated_emissions_kg: None,
+                }
+            })
+            .collect();
+        let total_cost = self.cost.saturating_mul(self.passengers as i64);
+        let fare = FareOption {
+            flight_ids: self.path.iter().map(|edge| edge.flight_id).collect(),
+            cabins: self.path.iter().map(|edge| edge.cabin).collect(),
+            total_cost,
+        };
+        Itinerary {
+            native_totals: totals_by_currency(&legs, self.passengers),
+            destination_id: self.current.to.read().unwrap().id,
+            legs,
+            passengers: self.passengers,
+            total_cost,
+            total_duration: self.total_duration(),
+            num_stops: self.path.len().saturating_sub(1),
+            estimated_emissions_kg: None,
+            fare_options: vec![fare],
+            completion_probability: self.completion_probability,
+            total_in_preferred: None,
+        }
+    }
+
+    /// Time from the first departure to the last arrival, layovers
+    /// included.
+    pub fn total_duration(&self) -> Duration {
+        self.current.arrive_utc - self.first_leg().depart_utc
+    }
+
+    // At most as dear and as long as `other`, and strictly better on one.
+    fn dominates(&self, other: &PathState) -> bool {
+        let (duration, other_duration) = (self.total_duration(), other.total_duration());
+        self.cost <= other.cost
+            && duration <= other_duration
+            && (self.cost < other.cost || duration < other_duration)
+    }
+
+    // How far local time at the origin is ahead of UTC at departure.
+    fn origin_offset(&self) -> Duration {
+        let first = self.first_leg();
+        first.depart_at - first.depart_utc
+    }
+
+    /// Time spent on the ground between each pair of consecutive legs.
+    pub fn layover_durations(&self) -> Vec<Duration> {
+        self.path
+            .windows(2)
+            .map(|legs| legs[1].depart_utc - legs[0].arrive_utc)
+            .collect()
+    }
+
+    fn longest_layover(&self) -> Duration {
+        self.layover_durations()
+            .into_iter()
+            .max()
+            .unwrap_or_else(Duration::zero)
+    }
 }
 
 impl Search {
     pub fn new(airports: AirportsContainer) -> Self {
         Search {
             airports: Arc::new(RwLock::new(airports)),
+            pool: None,
+            emission_factor: None,
+            misconnect_rates: Arc::new(HashMap::new()),
+            converter: None,
+            preferred_currency: None,
+            cache: None,
+            parallel: false,
+            view: None,
+            permits: None,
+            queue_timeout: None,
         }
     }
 
-    pub fn find(&self, query: SearchQuery) -> Vec<PathState> {
-        let airports_guard = self.airports.read().unwrap();
-        let airports = &airports_guard.airports;
-        
-        let from = match airports.get(&query.from) {
-            Some(airport) => airport.clone(),
-            None => return vec![],
-        };
-        let to = match airports.get(&query.to) {
-            Some(airport) => airport.clone(),
-            None => return vec![],
-        };
-        let date = NaiveDate::parse_from_str(&query.date, "%Y-%m-%d").unwrap();
-        let mut found: Vec<PathState> = self
-            .traverse(
-                from,
-                to,
-                date.and_hms_opt(0, 0, 0).unwrap(),
-                query.hops,
-                query.results,
-                &airports_guard,
-            )
-            .into_sorted_vec()
-            .into_iter()
-            .map(|Reverse(x)| x)
-            .collect();
-        found.reverse();
-        return found;
+    /// Builds and filters the first legs of a search in parallel, on the
+    /// pool from `with_pool` if set, otherwise on rayon's global pool. Worth
+    /// it for origins with thousands of departures a day; results are the
+    /// same either way.
+    pub fn with_parallel_seeding(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
     }
 
-    pub async fn find_async(&self, query: SearchQuery) -> Vec<PathState> {
-        let airports_arc = Arc::clone(&self.airports);
-        
-        let results = task::spawn_blocking(move || {
-            let search = Search { airports: airports_arc };
-            search.find(query)
-        })
-        .await
-        .unwrap_or_default();
-        
-        results
+    /// Looks up connections in a `SearchView` of the graph instead of
+    /// locking each airport along the way, so many concurrent searches
+    /// contend less. Seeding first legs and filtering states still take
+    /// the per-airport read locks. The first search after the graph changes
+    /// rebuilds the view. Results are the same either way.
+    pub fn with_read_view(mut self, enabled: bool) -> Self {
+        self.view = enabled.then(|| {
+            let view = self.airports.read().unwrap().read_view();
+            Arc::new(Mutex::new(Arc::new(view)))
+        });
+        self
     }
 
-    fn traverse(
-        &self,
-        source: Arc<RwLock<Airport>>,
-        target: Arc<RwLock<Airport>>,
-        date: NaiveDateTime,
-        k: usize,
-        total: usize,
-        airports_container: &AirportsContainer,
-    ) -> BinaryHeap<Reverse<PathState>> {
-        let num_nodes = airports_container
-            .flights_container
-            .read()
-            .unwrap()
-            .flights
-            .len();
-        let mut heap = BinaryHeap::new();
-        let mut count = vec![0; num_nodes];
-
-        let flights = source
-            .read()
-            .unwrap()
-            .flights_between(date, Some(date + Duration::hours(24)));
-        for flight in flights {
-            let edge = flight.clone();
-            if edge.depart_at < date {
-                continue;
-            }
-            let new_state = PathState {
-                cost: edge.cost,
-                current: Arc::clone(&flight),
-                path: vec![Arc::clone(&flight)],
-            };
-            heap.push(new_state);
+    // The view from `with_read_view`, first rebuilt if `airports` changed
+    // since it was taken.
+    fn read_view(&self, airports: &AirportsContainer) -> Option<Arc<SearchView>> {
+        let mut view = self.view.as_ref()?.lock().unwrap();
+        if view.version() != airports.data_version() {
+            *view = Arc::new(airports.read_view());
         }
+        Some(view.clone())
+    }
 
-        let mut results = BinaryHeap::new();
+    /// Keeps the results of the last `capacity` distinct queries passed to
+    /// `find`. Entries from before the last change to the graph are never
+    /// returned; they age out as new ones are added.
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        self.cache = Some(Arc::new(Mutex::new(LruCache::new(capacity))));
+        self
+    }
 
-        while let Some(state) = heap.pop() {
-            let curr = state.current.clone();
-            let cur_id = curr.flight_id;
-            count[cur_id] += 1;
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap().clear();
+        }
+    }
 
-            if curr.to.read().unwrap().id == target.read().unwrap().id {
-                results.push(Reverse(state.clone()));
-                if results.len() == total {
-                    return results;
+    /// Writes the cached queries and their results to `path` as JSON, so a
+    /// restarted service can reload them with `load_cache`.
+    pub fn dump_cache(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut entries = vec![];
+        if let Some(cache) = &self.cache {
+            let version = self.airports.read().unwrap().data_version();
+            // Least recently used first, so reloading restores the order.
+            for ((entry_version, query), results) in cache.lock().unwrap().iter().rev() {
+                if *entry_version != version {
+                    continue;
                 }
+                entries.push(CachedSearch {
+                    query: serde_json::from_str(query)?,
+                    itineraries: results
+                        .iter()
+                        .map(|x| x.path.iter().map(|edge| edge.flight_id).collect())
+                        .collect(),
+                });
             }
-            if state
-                .path
-                .iter()
-                .find(|x| x.from.read().unwrap().id == curr.to.read().unwrap().id)
-                .is_some()
-            {
+        }
+        serde_json::to_writer(File::create(path)?, &entries)?;
+        Ok(())
+    }
+
+    /// Loads entries written by `dump_cache` into this search's cache and
+    /// returns how many were kept. Entries are discarded as stale when
+    /// their date falls outside the dates the graph has flights on, or
+    /// when any of their flights is gone or no longer satisfies the query.
+    /// Does nothing without `with_cache`.
+    pub fn load_cache(&self, path: &str) -> Result<usize, Box<dyn Error>> {
+        let Some(cache) = &self.cache else {
+            return Ok(0);
+        };
+        let entries: Vec<CachedSearch> = serde_json::from_reader(File::open(path)?)?;
+        let airports_guard = self.airports.read().unwrap();
+        let flights = airports_guard.flights_container.read().unwrap();
+        let dates: Vec<NaiveDate> = flights.flights.iter().map(|x| x.depart_at.date()).collect();
+        let (Some(first), Some(last)) = (dates.iter().min(), dates.iter().max()) else {
+            return Ok(0);
+        };
+        let mut loaded = 0;
+        for entry in entries {
+            let covered = NaiveDate::parse_from_str(&entry.query.date, "%Y-%m-%d")
+                .is_ok_and(|date| *first <= date && date <= *last);
+            if !covered {
                 continue;
             }
-            if count[cur_id] > k {
-                continue;
+            let results: Option<Vec<PathState>> = entry
+                .itineraries
+                .iter()
+                .map(|ids| {
+                    let legs: Option<Vec<Arc<FlightEdge>>> =
+                        ids.iter().map(|id| flights.get_flight(*id)).collect();
+                    self.restore(&legs?, &entry.query)
+                })
+                .collect();
+            if let Some(results) = results {
+                let key = (
+                    airports_guard.data_version(),
+                    serde_json::to_string(&entry.query)?,
+                );
+                cache.lock().unwrap().put(key, results);
+                loaded += 1;
             }
+        }
+        Ok(loaded)
+    }
 
-            let start_date = curr.arrive_at + Duration::minutes(15);
-            let end_date = Some(date + Duration::hours(24));
+    // Rebuilds the state `find` would have produced for `legs`, or None if
+    // the legs no longer make a valid itinerary for `query`.
+    fn restore(&self, legs: &[Arc<FlightEdge>], query: &SearchQuery) -> Option<PathState> {
+        let (first, rest) = legs.split_first()?;
+        let mut state = PathState::start(first, self.leg_cost(first)?).filtered(query)?;
+        for flight in rest {
+            let connection = self.connection_success(state.current.flight_id, flight.flight_id);
+            state = state
+                .extend(flight, self.leg_cost(flight)?)?
+                .filtered(query)?;
+            state.completion_probability *= connection;
+        }
+        let date = NaiveDate::parse_from_str(&query.date, "%Y-%m-%d").ok()?;
+        let offset = (state.departure_date() - date).num_days();
+        state.penalty = state.penalty.saturating_add(query.flex_penalty(offset));
+        Some(state)
+    }
 
-            if start_date > end_date.unwrap() {
-                continue;
-            }
-            let dest_airport = state.current.clone().to.clone();
-            let flights = dest_airport
-                .read()
-                .unwrap()
-                .flights_between(start_date, end_date);
+    /// Supplies misconnect probabilities keyed by (arriving flight id,
+    /// connecting flight id), used for `PathState::completion_probability`
+    /// and `Objective::Reliability`.
+    pub fn with_misconnect_rates(mut self, rates: HashMap<(usize, usize), f64>) -> Self {
+        self.misconnect_rates = Arc::new(rates);
+        self
+    }
 
-            for flight in &flights {
-                let edge = flight.clone();
-                if edge.arrive_at > date + Duration::hours(48) {
-                    continue;
-                }
-                if edge.to.read().unwrap().id == source.read().unwrap().id {
-                    continue;
-                }
+    fn connection_success(&self, arriving: usize, departing: usize) -> f64 {
+        1.0 - self
+            .misconnect_rates
+            .get(&(arriving, departing))
+            .copied()
+            .unwrap_or(0.0)
+    }
 
-                let new_cost = state.cost + edge.cost;
-                let mut new_path = state.path.clone();
-                new_path.push(Arc::clone(flight));
-                let new_state = PathState {
-                    cost: new_cost,
-                    current: Arc::clone(flight),
-                    path: new_path,
-                };
-                heap.push(new_state);
-            }
-        }
-        results
+    /// Reports estimated emissions on structured results, computed as
+    /// `kg_per_distance` times each leg's distance for each passenger.
+    pub fn with_emission_factor(mut self, kg_per_distance: f64) -> Self {
+        self.emission_factor = Some(kg_per_distance);
+        self
     }
-}
 
+    /// Converts every leg's fare to `converter`'s base currency as the
+    /// search adds it up, so itineraries mixing currencies are ranked and
+    /// totalled comparably. Costs on `PathState`, `Itinerary::total_cost`
+    /// and `SearchQuery::max_cost` are then in the base currency; each
+    /// `LegInfo` keeps its native fare.
+    pub fn with_currency_converter(mut self, converter: Arc<dyn CurrencyConverter>) -> Self {
+        self.converter = Some(converter);
+        self
+    }
+
+    // Fare of `flight` in the converter's base currency, or as listed when
+    // there is no converter. None if the converter cannot convert it, in
+    // which case the flight is never flown.
+    fn leg_cost(&self, flight: &FlightEdge) -> Option<i64> {
+        match &self.converter {
+            Some(converter) => converter.to_base(flight.cost, &flight.currency),
+            None => Some(flight.cost),
+        }
+    }
+
+    /// Reports each structured result's total in `currency` as well, via
+    /// the search's currency converter.
+    pub fn with_preferred_currency(mut self, currency: &str) -> Self {
+        self.preferred_currency = Some(currency.to_string());
+        self
+    }
+
+    /// Runs `find_async` traversals on `pool` instead of tokio's blocking
+    /// pool, capping concurrent searches at the pool's thread count.
+    pub fn with_pool(mut self, pool: ThreadPool) -> Self {
+        self.pool = Some(Arc::new(pool));
+        self
+    }
+
+    /// Runs at most `n` `find_async` searches at once; further calls queue
+    /// until one finishes, so a burst of requests cannot exhaust the
+    /// blocking pool. Non-zero, as no search could ever start otherwise.
+    pub fn with_concurrency(mut self, n: NonZeroUsize) -> Self {
+        self.permits = Some(Arc::new(Semaphore::new(n.get())));
+        self
+    }
+
+    /// Fails a `find_async` that has queued for longer than `timeout` under
+    /// `with_concurrency` with `SearchError::Overloaded`. Queued searches
+    /// wait indefinitely otherwise.
+    pub fn with_queue_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    /// Best itineraries for `query`. Fails if `query.date` is not a
+    /// "YYYY-MM-DD" date, if either endpoint matches no airport, or with
+    /// `SearchError::NoRouteFound` if nothing connects them.
+    pub fn find(&self, query: SearchQuery) -> Result<Vec<PathState>, SearchError> {
+        self.find_until(query, None)
+    }
+
+    /// `find` with every failure collapsed into an empty list.
+    pub fn find_or_empty(&self, query: SearchQuery) -> Vec<PathState> {
+        self.find(query).unwrap_or_default()
+    }
+
+    /// `find` that stops early once `token` is cancelled, returning the
+    /// results found up to then. Cut-short results are not cached.
+    pub fn find_cancellable(
+        &self,
+        query: SearchQuery,
+        token: &CancellationToken,
+    ) -> Result<Vec<PathState>, SearchError> {
+        self.find_until(query, Some(token))
+    }
+
+    fn find_until(
+        &self,
+        query: SearchQuery,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<PathState>, SearchError> {
+        start_of_day(&query.date)?;
+        Self::resolve_endpoints(&self.airports.read().unwrap(), &query)?;
+        let key = self.cache.as_ref().map(|_| {
+            let version = self.airports.read().unwrap().data_version();
+            (version, serde_json::to_string(&query).unwrap())
+        });
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(found) = cache.lock().unwrap().get(key) {
+                return Self::found_or_no_route(found.clone());
+            }
+        }
+        let traversal = self.collect(&query, Self::wanted(&query), cancel);
+        let mut found = traversal.results;
+        found.truncate(query.results);
+        if let (Some(cache), Some(key)) = (&self.cache, key) {
+            if !traversal.cancelled {
+                cache.lock().unwrap().put(key, found.clone());
+            }
+        }
+        Self::found_or_no_route(found)
+    }
+
+    fn found_or_no_route(found: Vec<PathState>) -> Result<Vec<PathState>, SearchError> {
+        if found.is_empty() {
+            Err(SearchError::NoRouteFound)
+        } else {
+            Ok(found)
+        }
+    }
+
+    /// `find` together with the work the search did, for diagnosing slow
+    /// queries. Always searches, bypassing the result cache. Finding no
+    /// route is not an error here, so its stats are still returned.
+    pub fn find_with_stats(
+        &self,
+        query: SearchQuery,
+    ) -> Result<(Vec<PathState>, SearchStats), SearchError> {
+        start_of_day(&query.date)?;
+        Self::resolve_endpoints(&self.airports.read().unwrap(), &query)?;
+        let traversal = self.collect(&query, Self::wanted(&query), None);
+        let mut found = traversal.results;
+        found.truncate(query.results);
+        Ok((found, traversal.stats))
+    }
+
+    // How many itineraries `find` collects before cutting them down to
+    // `query.results`.
+    fn wanted(query: &SearchQuery) -> usize {
+        if query.objective.needs_candidates() {
+            query.results * CANDIDATE_MULTIPLIER
+        } else {
+            query.results
+        }
+    }
+
+    /// Best itineraries to `to` from whichever of `origins` gives them, e.g.
+    /// every airport within reach of the traveller. The same as `find` with
+    /// `SearchQuery::origins` set.
+    pub fn find_multi_origin(
+        &self,
+        origins: Vec<usize>,
+        to: usize,
+        date: &str,
+        hops: usize,
+        results: usize,
+    ) -> Result<Vec<PathState>, SearchError> {
+        self.find(SearchQuery {
+            origins: Some(origins),
+            to,
+            date: date.to_string(),
+            hops,
+            results,
+            ..Default::default()
+        })
+    }
+
+    /// Best itineraries from `from` ending at any of `targets`. The same as
+    /// `find` with `SearchQuery::destinations` set; each result's
+    /// `Itinerary::destination_id` says which target it reached.
+    pub fn find_multi_dest(
+        &self,
+        from: usize,
+        targets: HashSet<usize>,
+        date: &str,
+        hops: usize,
+        results: usize,
+    ) -> Result<Vec<PathState>, SearchError> {
+        let mut destinations: Vec<usize> = targets.into_iter().collect();
+        // Sorted so equal target sets give equal queries, and share a cache
+        // entry.
+        destinations.sort_unstable();
+        self.find(SearchQuery {
+            from,
+            destinations: Some(destinations),
+            date: date.to_string(),
+            hops,
+            results,
+            ..Default::default()
+        })
+    }
+
+    /// The cheapest itinerary from `from` to `to` departing on `date`
+    /// ("YYYY-MM-DD"): the one `find` ranks first with `results: 1` and
+    /// otherwise default options. It explores as `find` does, with the same
+    /// per-airport `hops` cap, dominance pruning and tie-breaking, but keeps
+    /// partial paths as links to their previous leg rather than copies, and
+    /// stops the first time it reaches `to`, so it is much lighter. None
+    /// when nothing connects them or the date is invalid.
+    pub fn find_cheapest(
+        &self,
+        from: usize,
+        to: usize,
+        date: &str,
+        hops: usize,
+    ) -> Option<PathState> {
+        let query = SearchQuery {
+            from,
+            to,
+            date: date.to_string(),
+            hops,
+            results: 1,
+            ..Default::default()
+        };
+        let day = start_of_day(date).ok()?;
+        let airports_guard = self.airports.read().unwrap();
+        let (sources, endpoints) = Self::resolve_endpoints(&airports_guard, &query).ok()?;
+        let view = self.read_view(&airports_guard);
+        // Indexed in the order they were pushed.
+        let mut labels: Vec<Label> = vec![];
+        // Cheapest first; equal costs in the order they were pushed, as on
+        // `find`'s frontier.
+        let mut heap = BinaryHeap::new();
+        for seed in self.seed(&sources, day, &query) {
+            if endpoints.sources.contains(&seed.at) && !endpoints.targets.contains(&seed.at) {
+                continue;
+            }
+            heap.push(Reverse((seed.cost, labels.len())));
+            labels.push(Label {
+                origin_offset: seed.origin_offset(),
+                flight: seed.current,
+                at: seed.at,
+                cost: seed.cost,
+                legs: 1,
+                previous: None,
+            });
+        }
+        // As in `traverse_with`, no airport is continued from more than
+        // `hops` times, and a leg is not pushed if a path continued from
+        // the same airport in as many legs is cheaper, arrives earlier and
+        // visited no airport the new one has not.
+        let mut count: HashMap<usize, usize> = HashMap::new();
+        let mut continued: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        while let Some(Reverse((_, index))) = heap.pop() {
+            let label = &labels[index];
+            if endpoints.targets.contains(&label.at) {
+                let mut legs = vec![];
+                let mut leg = Some(index);
+                while let Some(index) = leg {
+                    legs.push(labels[index].flight.clone());
+                    leg = labels[index].previous;
+                }
+                legs.reverse();
+                return self.restore(&legs, &query);
+            }
+            let expansions = count.entry(label.at).or_default();
+            *expansions += 1;
+            if *expansions > hops || query.nonstop_only() {
+                continue;
+            }
+            continued
+                .entry((label.at, label.legs))
+                .or_default()
+                .push(index);
+            let start_date = label.flight.arrive_utc + Duration::minutes(MIN_CONNECTION_MINUTES);
+            let end_date =
+                day + Duration::hours(DEFAULT_SEARCH_WINDOW_HOURS as i64) - label.origin_offset;
+            if start_date > end_date {
+                continue;
+            }
+            let locked;
+            let flights = match &view {
+                Some(view) => view.departures(label.at, start_date, end_date),
+                None => {
+                    locked = Self::departures(&label.flight, start_date, end_date);
+                    &locked[..]
+                }
+            };
+            let mut visited: Option<HashSet<usize>> = None;
+            let mut reached = vec![];
+            for ViewFlight {
+                flight: edge, to, ..
+            } in flights
+            {
+                if edge.seats == 0
+                    || !Self::within_horizon(edge, day, label.origin_offset, &query)
+                    || endpoints.sources.contains(to)
+                    || Self::on_path(&labels, index, *to)
+                {
+                    continue;
+                }
+                let Some(cost) = self.leg_cost(edge).and_then(|x| label.cost.checked_add(x)) else {
+                    continue;
+                };
+                if let Some(rivals) = continued.get(&(*to, label.legs + 1)) {
+                    let visited = visited.get_or_insert_with(|| {
+                        Self::path_airports(&labels, index).into_iter().collect()
+                    });
+                    let dominated = rivals.iter().any(|&rival| {
+                        let other = &labels[rival];
+                        other.cost < cost
+                            && other.flight.arrive_utc < edge.arrive_utc
+                            && other.origin_offset == label.origin_offset
+                            && Self::path_airports(&labels, rival)
+                                .iter()
+                                .all(|x| x == to || visited.contains(x))
+                    });
+                    if dominated {
+                        continue;
+                    }
+                }
+                reached.push(Label {
+                    flight: edge.clone(),
+                    at: *to,
+                    cost,
+                    origin_offset: label.origin_offset,
+                    legs: label.legs + 1,
+                    previous: Some(index),
+                });
+            }
+            for label in reached {
+                heap.push(Reverse((label.cost, labels.len())));
+                labels.push(label);
+            }
+        }
+        None
+    }
+
+    // Every airport on the path ending at `labels[index]`, origin included.
+    fn path_airports(labels: &[Label], index: usize) -> Vec<usize> {
+        let mut airports = vec![];
+        let mut leg = Some(index);
+        while let Some(index) = leg {
+            let label = &labels[index];
+            airports.push(label.at);
+            if label.previous.is_none() {
+                airports.push(label.flight.from.read().unwrap().id);
+            }
+            leg = label.previous;
+        }
+        airports
+    }
+
+    // Whether the path ending at `labels[index]` passes through `airport`.
+    fn on_path(labels: &[Label], index: usize, airport: usize) -> bool {
+        let mut leg = Some(index);
+        while let Some(index) = leg {
+            let label = &labels[index];
+            if label.at == airport {
+                return true;
+            }
+            leg = label.previous;
+        }
+        false
+    }
+
+    /// Every nonstop flight from `from` to `to` departing on `date`
+    /// ("YYYY-MM-DD") with a seat left, cheapest first. The same as `find`
+    /// with `hops: 1`, which like searches with a hard
+    /// `Constraint::MaxStops(0)` filter skips the general traversal.
+    pub fn direct_flights(&self, from: usize, to: usize, date: &str) -> Vec<PathState> {
+        let query = SearchQuery {
+            from,
+            to,
+            date: date.to_string(),
+            hops: 1,
+            results: usize::MAX,
+            ..Default::default()
+        };
+        self.collect(&query, usize::MAX, None).results
+    }
+
+    /// Outbound itineraries for `outbound` paired with return itineraries
+    /// on `return_date`, cheapest combined price first and at most
+    /// `outbound.results` pairs. The return leaves the outbound's arrival
+    /// airport for its origin no sooner than `min_stay_hours` after landing.
+    /// Filters and preferences apply to both directions; the outbound's
+    /// departure window and arrival deadline do not apply to the return.
+    pub fn find_round_trip(
+        &self,
+        outbound: SearchQuery,
+        return_date: String,
+        min_stay_hours: i64,
+    ) -> Vec<(PathState, PathState)> {
+        let Ok(return_day) = NaiveDate::parse_from_str(&return_date, "%Y-%m-%d") else {
+            tracing::warn!(date = %return_date, "ignoring round trip with invalid return date");
+            return vec![];
+        };
+        if start_of_day(&outbound.date).is_err() {
+            tracing::warn!(date = %outbound.date, "ignoring round trip with invalid outbound date");
+            return vec![];
+        }
+        let outbounds = self.find_or_empty(outbound.clone());
+        let return_start = return_day.and_hms_opt(0, 0, 0).unwrap();
+        let results = outbound.results;
+        // Outbounds landing at the same airport and time share one return
+        // search.
+        let mut returns: HashMap<(usize, usize, NaiveDateTime), Vec<PathState>> = HashMap::new();
+        let mut pairs = vec![];
+        for out in outbounds {
+            let origin = out.first_leg().from.read().unwrap().id;
+            let destination = out.current.to.read().unwrap().id;
+            let earliest = out.current.arrive_at + Duration::hours(min_stay_hours);
+            let inbound = returns
+                .entry((destination, origin, earliest))
+                .or_insert_with(|| {
+                    if earliest >= return_start + Duration::days(1) {
+                        return vec![];
+                    }
+                    let depart_after =
+                        (earliest > return_start).then(|| earliest.format("%H:%M").to_string());
+                    let query = SearchQuery {
+                        from: destination,
+                        to: origin,
+                        date: return_date.clone(),
+                        from_code: None,
+                        to_code: None,
+                        origins: None,
+                        destinations: None,
+                        from_name: None,
+                        to_name: None,
+                        force_first: None,
+                        force_last: None,
+                        arrive_before: None,
+                        depart_after,
+                        depart_before: None,
+                        ..outbound.clone()
+                    };
+                    let mut found = self.find_or_empty(query);
+                    // `depart_after` does not hold on flexible dates other
+                    // than `return_date`.
+                    found.retain(|x| x.first_leg().depart_at >= earliest);
+                    found
+                });
+            for back in inbound.iter() {
+                pairs.push((out.clone(), back.clone()));
+            }
+        }
+        pairs.sort_by_key(|(out, back)| out.cost.saturating_add(back.cost));
+        pairs.truncate(results);
+        pairs
+    }
+
+    /// Every candidate itinerary that no other candidate beats on both price
+    /// and total duration, cheapest first. Candidates are gathered as for
+    /// `find_result_set`, so the frontier is over that wider set and is not
+    /// cut to `query.results`.
+    pub fn pareto_frontier(&self, query: SearchQuery) -> Vec<PathState> {
+        let candidates = self
+            .collect(&query, query.results * CANDIDATE_MULTIPLIER, None)
+            .results;
+        let mut frontier: Vec<PathState> = candidates
+            .iter()
+            .filter(|x| !candidates.iter().any(|other| other.dominates(x)))
+            .cloned()
+            .collect();
+        frontier.sort_by_key(|x| (x.cost, x.total_duration()));
+        frontier
+    }
+
+    /// Itineraries that no other itinerary beats on both price and total
+    /// duration, cheapest first. Unlike `pareto_frontier`, the search is not
+    /// cut off after a fixed number of candidates: it weighs every itinerary
+    /// within `query.hops` and the trip horizon, keeping only the frontier
+    /// as it goes. `query.results` and `query.objective` are ignored.
+    pub fn find_pareto(&self, query: SearchQuery) -> Vec<Itinerary> {
+        let airports_guard = self.airports.read().unwrap();
+        let Ok((sources, endpoints)) = Self::resolve_endpoints(&airports_guard, &query) else {
+            return vec![];
+        };
+        let Ok(date) = start_of_day(&query.date) else {
+            tracing::warn!(date = %query.date, "ignoring search with invalid date");
+            return vec![];
+        };
+        let query = SearchQuery {
+            objective: Objective::Cost,
+            ..query
+        };
+        let view = self.read_view(&airports_guard);
+        let mut frontier: Vec<PathState> = vec![];
+        let flex = query.date_flex_days as i64;
+        for offset in -flex..=flex {
+            let day = date + Duration::days(offset);
+            let seeds = self.seed(&sources, day, &query);
+            self.traverse_with(
+                seeds,
+                &endpoints,
+                day,
+                usize::MAX,
+                &query,
+                view.as_deref(),
+                None,
+                &mut |state| {
+                    if !frontier.iter().any(|x| x == state || x.dominates(state)) {
+                        frontier.retain(|x| !state.dominates(x));
+                        frontier.push(state.clone());
+                    }
+                    true
+                },
+            );
+        }
+        frontier.sort_by_key(|x| (x.cost, x.total_duration()));
+        frontier.iter().map(|x| self.itinerary(x)).collect()
+    }
+
+    /// Best itineraries for `query` as ranked by `cmp` rather than the
+    /// query's objective, e.g. earliest arrival first. Candidates are
+    /// gathered as for `find_result_set`, sorted stably with `cmp` so ties
+    /// keep the objective's order, and cut to `query.results`. Bypasses the
+    /// result cache.
+    pub fn find_ranked(
+        &self,
+        query: SearchQuery,
+        cmp: impl Fn(&PathState, &PathState) -> Ordering,
+    ) -> Result<Vec<PathState>, SearchError> {
+        start_of_day(&query.date)?;
+        Self::resolve_endpoints(&self.airports.read().unwrap(), &query)?;
+        let mut found = self
+            .collect(&query, query.results * CANDIDATE_MULTIPLIER, None)
+            .results;
+        found.sort_by(|a, b| cmp(a, b));
+        found.truncate(query.results);
+        Self::found_or_no_route(found)
+    }
+
+    /// Searches for more candidates than `query.results` and keeps them, so
+    /// the result can be narrowed with `SearchResultSet::refine` without
+    /// traversing again.
+    pub fn find_result_set(&self, query: SearchQuery) -> SearchResultSet {
+        let found = self.collect(&query, query.results * CANDIDATE_MULTIPLIER, None);
+        SearchResultSet {
+            results: found.results.iter().take(query.results).cloned().collect(),
+            candidates: found.results,
+            limit: query.results,
+            degraded: found.degraded,
+            stats: found.stats,
+        }
+    }
+
+    // Origin airports and the endpoint ids for `query`, or which side
+    // matches no airport.
+    fn resolve_endpoints(
+        airports_guard: &AirportsContainer,
+        query: &SearchQuery,
+    ) -> Result<(Sources, Endpoints), SearchError> {
+        let mut sources = Self::endpoints(
+            airports_guard,
+            query.from,
+            &query.origins,
+            &query.from_code,
+            &query.from_name,
+        );
+        let mut targets: HashSet<usize> = Self::endpoints(
+            airports_guard,
+            query.to,
+            &query.destinations,
+            &query.to_code,
+            &query.to_name,
+        )
+        .iter()
+        .map(|airport| airport.read().unwrap().id)
+        .collect();
+        if let Some(first) = query.force_first {
+            sources.retain(|airport| airport.read().unwrap().id == first);
+        }
+        if let Some(last) = query.force_last {
+            targets.retain(|id| *id == last);
+        }
+        if sources.is_empty() {
+            return Err(SearchError::UnknownOrigin);
+        }
+        if targets.is_empty() {
+            return Err(SearchError::UnknownDestination);
+        }
+        let endpoints = Endpoints {
+            sources: sources
+                .iter()
+                .map(|airport| airport.read().unwrap().id)
+                .collect(),
+            targets,
+        };
+        Ok((sources, endpoints))
+    }
+
+    // Up to `total` itineraries for `query`, ranked by its objective.
+    fn collect(
+        &self,
+        query: &SearchQuery,
+        total: usize,
+        cancel: Option<&CancellationToken>,
+    ) -> Traversal {
+        let airports_guard = self.airports.read().unwrap();
+        let Ok((sources, endpoints)) = Self::resolve_endpoints(&airports_guard, query) else {
+            return Traversal::default();
+        };
+        let Ok(date) = start_of_day(&query.date) else {
+            tracing::warn!(date = %query.date, "ignoring search with invalid date");
+            return Traversal::default();
+        };
+        let view = self.read_view(&airports_guard);
+        let flex = query.date_flex_days as i64;
+        let mut traversal = Traversal::default();
+        for offset in -flex..=flex {
+            let day = date + Duration::days(offset);
+            let seeds = self.seed(&sources, day, query);
+            let found = self.traverse_with(
+                seeds,
+                &endpoints,
+                day,
+                total,
+                query,
+                view.as_deref(),
+                cancel,
+                &mut |_| true,
+            );
+            let penalty = query.flex_penalty(offset);
+            traversal.degraded |= found.degraded;
+            traversal.cancelled |= found.cancelled;
+            traversal.stats.merge(found.stats);
+            traversal
+                .results
+                .extend(found.results.into_iter().map(|mut state| {
+                    state.penalty = state.penalty.saturating_add(penalty);
+                    state
+                }));
+        }
+        if flex > 0 {
+            traversal.results.sort_by_key(|x| x.rank());
+        }
+        if !query.allow_duplicates {
+            let mut seen = HashSet::new();
+            traversal
+                .results
+                .retain(|x| seen.insert(x.path.iter().map(|x| x.flight_id).collect::<Vec<_>>()));
+        }
+        traversal.results.truncate(total);
+        let found = &mut traversal.results;
+        // Shuffled before the objective's stable sort, so ties under the
+        // objective end up shuffled too.
+        if let Some(seed) = query.tie_break_seed {
+            let mut rng = StdRng::seed_from_u64(seed);
+            for ties in found.chunk_by_mut(|a, b| a.rank() == b.rank()) {
+                ties.shuffle(&mut rng);
+            }
+        }
+
+        match query.objective {
+            Objective::BestValue => Self::rank_best_value(found),
+            Objective::MaxLayover => found.sort_by(|a, b| {
+                b.longest_layover()
+                    .cmp(&a.longest_layover())
+                    .then(a.cost.cmp(&b.cost))
+            }),
+            Objective::Reliability => found.sort_by(|a, b| {
+                b.completion_probability
+                    .partial_cmp(&a.completion_probability)
+                    .unwrap_or(Ordering::Equal)
+                    .then(a.cost.cmp(&b.cost))
+            }),
+            Objective::Personalized => Self::rank_personalized(found, &query.preferences),
+            Objective::Cost
+            | Objective::EarliestArrival
+            | Objective::Duration
+            | Objective::Hops => {}
+        }
+        traversal
+    }
+
+    /// Onward itineraries to `to` that start with the already chosen flight
+    /// `first_flight_id`, e.g. once a user has picked their outbound leg.
+    /// Connections from the pinned flight follow the usual layover rules.
+    pub fn find_continuations(
+        &self,
+        first_flight_id: usize,
+        to: usize,
+        hops: usize,
+        results: usize,
+    ) -> Vec<PathState> {
+        let airports_guard = self.airports.read().unwrap();
+        let pinned = airports_guard
+            .flights_container
+            .read()
+            .unwrap()
+            .get_flight(first_flight_id);
+        let pinned = match pinned {
+            Some(flight) => flight,
+            None => return vec![],
+        };
+        let Some(cost) = self.leg_cost(&pinned) else {
+            return vec![];
+        };
+        if !airports_guard.has_airport(to) {
+            return vec![];
+        }
+        let from = pinned.from.read().unwrap().id;
+        let date = pinned.depart_at.date().and_hms_opt(0, 0, 0).unwrap();
+        let query = SearchQuery {
+            from,
+            to,
+            date: date.format("%Y-%m-%d").to_string(),
+            hops,
+            results,
+            ..Default::default()
+        };
+        let endpoints = Endpoints {
+            sources: HashSet::from([from]),
+            targets: HashSet::from([to]),
+        };
+        let view = self.read_view(&airports_guard);
+        self.traverse(
+            vec![PathState::start(&pinned, cost)],
+            &endpoints,
+            date,
+            results,
+            &query,
+            view.as_deref(),
+        )
+        .results
+    }
+
+    /// Inspiration search: the cheapest itinerary from `from` on `date` to
+    /// each airport reachable in at most `max_hops` legs, cheapest
+    /// destination first. Returns at most `results` destinations.
+    pub fn cheapest_anywhere(
+        &self,
+        from: usize,
+        date: &str,
+        max_hops: usize,
+        results: usize,
+    ) -> Vec<PathState> {
+        let airports_guard = self.airports.read().unwrap();
+        let Some(source) = airports_guard.airports.get(&from).map(|x| x.clone()) else {
+            return vec![];
+        };
+        let query = SearchQuery {
+            from,
+            date: date.to_string(),
+            hops: max_hops,
+            results,
+            filters: FilterSet::default().hard(Constraint::MaxStops(max_hops.saturating_sub(1))),
+            ..Default::default()
+        };
+        let endpoints = Endpoints {
+            sources: HashSet::from([from]),
+            targets: airports_guard
+                .airports
+                .iter()
+                .map(|x| *x.key())
+                .filter(|id| *id != from)
+                .collect(),
+        };
+        let Ok(date) = start_of_day(date) else {
+            tracing::warn!(date = %date, "ignoring search with invalid date");
+            return vec![];
+        };
+        let seeds = self.seed(&[source], date, &query);
+        // Every itinerary reaching any airport, cheapest first; the first
+        // one to reach each airport is the cheapest to it.
+        let view = self.read_view(&airports_guard);
+        let found = self.traverse(seeds, &endpoints, date, usize::MAX, &query, view.as_deref());
+        let mut seen = HashSet::new();
+        found
+            .results
+            .into_iter()
+            .filter(|x| seen.insert(x.current.to.read().unwrap().id))
+            .take(results)
+            .collect()
+    }
+
+    /// Every flight leaving any of `origins` within `window` of `from_time`,
+    /// in departure order. A schedule lookup, not a path search.
+    pub fn departures_board(
+        &self,
+        origins: &[usize],
+        from_time: NaiveDateTime,
+        window: Duration,
+    ) -> Vec<Arc<FlightEdge>> {
+        let airports_guard = self.airports.read().unwrap();
+        let mut departures: Vec<Arc<FlightEdge>> = origins
+            .iter()
+            .filter_map(|id| {
+                airports_guard
+                    .airports
+                    .get(id)
+                    .map(|airport| airport.clone())
+            })
+            .flat_map(|airport| {
+                airport
+                    .read()
+                    .unwrap()
+                    .flights_between(from_time, Some(from_time + window))
+            })
+            .collect();
+        departures.sort_by_key(|x| (x.depart_at, x.flight_id));
+        departures
+    }
+
+    // Airports a query endpoint refers to: `ids` when given, then the
+    // airport with `code`, then every match of `name`, otherwise the
+    // airport with `id`. Unknown airports are dropped.
+    fn endpoints(
+        container: &AirportsContainer,
+        id: usize,
+        ids: &Option<Vec<usize>>,
+        code: &Option<String>,
+        name: &Option<String>,
+    ) -> Vec<Arc<RwLock<Airport>>> {
+        let ids = match (ids, code, name) {
+            (Some(ids), _, _) => ids.clone(),
+            (None, Some(code), _) => container.get_airport_by_code(code).into_iter().collect(),
+            (None, None, Some(name)) => container.resolve_name(name),
+            (None, None, None) => vec![id],
+        };
+        ids.iter()
+            .filter_map(|id| container.airports.get(id).map(|airport| airport.clone()))
+            .collect()
+    }
+
+    // Scores every candidate as a weighted sum of its price and duration,
+    // each scaled to [0, 1] against the cheapest/priciest and
+    // fastest/slowest candidates. Lower is better.
+    fn rank_best_value(candidates: &mut [PathState]) {
+        if candidates.is_empty() {
+            return;
+        }
+        let costs: Vec<f64> = candidates.iter().map(|x| x.cost as f64).collect();
+        let durations: Vec<f64> = candidates
+            .iter()
+            .map(|x| x.total_duration().num_minutes() as f64)
+            .collect();
+        let score = |state: &PathState| {
+            BEST_VALUE_PRICE_WEIGHT * normalize(&costs, state.cost as f64)
+                + BEST_VALUE_DURATION_WEIGHT
+                    * normalize(&durations, state.total_duration().num_minutes() as f64)
+        };
+        candidates.sort_by(|a, b| {
+            score(a)
+                .partial_cmp(&score(b))
+                .unwrap_or(Ordering::Equal)
+                .then(a.cost.cmp(&b.cost))
+        });
+    }
+
+    fn rank_personalized(candidates: &mut [PathState], preferences: &Preferences) {
+        let costs: Vec<f64> = candidates.iter().map(|x| x.cost as f64).collect();
+        let durations: Vec<f64> = candidates
+            .iter()
+            .map(|x| x.total_duration().num_minutes() as f64)
+            .collect();
+        let max_stops = candidates
+            .iter()
+            .map(|x| x.path.len().saturating_sub(1))
+            .max()
+            .unwrap_or(0);
+        let score = |state: &PathState| {
+            let stops = match max_stops {
+                0 => 0.0,
+                max => state.path.len().saturating_sub(1) as f64 / max as f64,
+            };
+            let foreign_legs = state
+                .path
+                .iter()
+                .filter(|x| !preferences.preferred_airlines.contains(&x.airline))
+                .count();
+            preferences.price * normalize(&costs, state.cost as f64)
+                + preferences.duration
+                    * normalize(&durations, state.total_duration().num_minutes() as f64)
+                + preferences.stops * stops
+                + preferences.carrier_affinity * foreign_legs as f64 / state.path.len() as f64
+                + preferences.reliability * (1.0 - state.completion_probability)
+        };
+        candidates.sort_by(|a, b| {
+            score(a)
+                .partial_cmp(&score(b))
+                .unwrap_or(Ordering::Equal)
+                .then(a.cost.cmp(&b.cost))
+        });
+    }
+
+    /// Like `find`, but returns structured itineraries, including emissions
+    /// when an emission factor is configured. Results flying the same
+    /// flights in different cabins are grouped as fare options of one
+    /// itinerary.
+    pub fn find_itineraries(&self, query: SearchQuery) -> Result<Vec<Itinerary>, SearchError> {
+        let itineraries = self
+            .find(query)?
+            .iter()
+            .map(|state| self.itinerary(state))
+            .collect();
+        Ok(group_fare_options(itineraries))
+    }
+
+    pub fn itinerary(&self, state: &PathState) -> Itinerary {
+        let mut itinerary = state.to_itinerary();
+        if let Some(factor) = self.emission_factor {
+            itinerary = itinerary.with_emissions(factor);
+        }
+        if let (Some(converter), Some(currency)) = (&self.converter, &self.preferred_currency) {
+            itinerary = itinerary.with_preferred_currency(converter.as_ref(), currency);
+        }
+        itinerary
+    }
+
+    /// Runs `find` without blocking the async executor. Outside a tokio
+    /// runtime, and without a pool from `with_pool`, the search runs on the
+    /// calling thread instead.
+    pub async fn find_async(&self, query: SearchQuery) -> Result<Vec<PathState>, SearchError> {
+        self.find_async_until(query, None).await
+    }
+
+    /// `find_async` that stops early once `token` is cancelled, as
+    /// `find_cancellable` does.
+    pub async fn find_async_cancellable(
+        &self,
+        query: SearchQuery,
+        token: CancellationToken,
+    ) -> Result<Vec<PathState>, SearchError> {
+        self.find_async_until(query, Some(token)).await
+    }
+
+    async fn find_async_until(
+        &self,
+        query: SearchQuery,
+        cancel: Option<CancellationToken>,
+    ) -> Result<Vec<PathState>, SearchError> {
+        start_of_day(&query.date)?;
+        let permit = match &self.permits {
+            Some(permits) => Some(self.acquire(permits.clone()).await?),
+            None => None,
+        };
+        let search = self.clone();
+        // The permit is held until the search itself ends, even if the
+        // caller stops waiting for it.
+        let run = move || {
+            let _permit = permit;
+            search.find_until(query, cancel.as_ref())
+        };
+
+        match &self.pool {
+            Some(pool) => {
+                let (tx, rx) = oneshot::channel();
+                pool.spawn(move || {
+                    let _ = tx.send(run());
+                });
+                rx.await.unwrap_or(Err(SearchError::Internal))
+            }
+            // Outside a tokio runtime there is no blocking pool to hand the
+            // search to, so it runs on the caller's thread instead.
+            None if Handle::try_current().is_err() => run(),
+            None => task::spawn_blocking(run)
+                .await
+                .unwrap_or(Err(SearchError::Internal)),
+        }
+    }
+
+    // One of `permits`, waiting at most the queue timeout for it. The
+    // timeout needs a tokio runtime; outside one the wait is unbounded.
+    async fn acquire(&self, permits: Arc<Semaphore>) -> Result<OwnedSemaphorePermit, SearchError> {
+        let acquire = permits.acquire_owned();
+        let permit = match self.queue_timeout {
+            Some(timeout) if Handle::try_current().is_ok() => time::timeout(timeout, acquire)
+                .await
+                .map_err(|_| SearchError::Overloaded)?,
+            _ => acquire.await,
+        };
+        // The semaphore is never closed.
+        Ok(permit.unwrap())
+    }
+
+    /// Streams the results of `find` as the search produces them, so the
+    /// first itineraries arrive before the search completes. Objectives
+    /// that rank the whole candidate set, flexible dates and tie-break
+    /// shuffling need every result before ranking; those stream only once
+    /// the search is complete. Dropping the stream stops the search. Without
+    /// a pool from `with_pool` this must be called within a tokio runtime.
+    pub fn find_stream(&self, query: SearchQuery) -> impl Stream<Item = PathState> {
+        let (tx, rx) = mpsc::channel(query.results.max(1));
+        let search = self.clone();
+        let run = move || search.stream_into(query, tx);
+        match &self.pool {
+            Some(pool) => pool.spawn(run),
+            None => {
+                task::spawn_blocking(run);
+            }
+        }
+        ReceiverStream::new(rx)
+    }
+
+    fn stream_into(&self, query: SearchQuery, tx: mpsc::Sender<PathState>) {
+        if query.results == 0 {
+            return;
+        }
+        let Ok(date) = start_of_day(&query.date) else {
+            tracing::warn!(date = %query.date, "ignoring search with invalid date");
+            return;
+        };
+        let incremental = !query.objective.needs_candidates()
+            && query.date_flex_days == 0
+            && query.tie_break_seed.is_none();
+        if !incremental {
+            for state in self.find_or_empty(query) {
+                if tx.blocking_send(state).is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+        let airports_guard = self.airports.read().unwrap();
+        let Ok((sources, endpoints)) = Self::resolve_endpoints(&airports_guard, &query) else {
+            return;
+        };
+        let seeds = self.seed(&sources, date, &query);
+        let view = self.read_view(&airports_guard);
+        self.traverse_with(
+            seeds,
+            &endpoints,
+            date,
+            query.results,
+            &query,
+            view.as_deref(),
+            None,
+            &mut |state| tx.blocking_send(state.clone()).is_ok(),
+        );
+    }
+
+    // Whether the search may drop a partial path that is both dearer and
+    // later than `total` paths already continued from the same airport in
+    // as many legs, each having visited no airport the dropped one has
+    // not. Any onward connection it could take, those could take too, and
+    // more cheaply, so the `total` cheapest itineraries are unchanged: the
+    // prune preserves optimality for the cost objective. Constraints on
+    // earlier flights (via airports, layover caps, airline and
+    // carrier-change limits) turn pruning off. Paths carrying a soft
+    // constraint penalty never dominate.
+    fn prunes_dominated(query: &SearchQuery) -> bool {
+        query.objective == Objective::Cost
+            && query.via.is_none()
+            && query.max_layover_minutes.is_none()
+            && query.max_carrier_changes.is_none()
+            && query.max_airlines.is_none()
+    }
+
+    // Whether `flight` lands in time for a search starting on `date`, where
+    // `origin_offset` is the itinerary origin's UTC offset.
+    fn within_horizon(
+        flight: &FlightEdge,
+        date: NaiveDateTime,
+        origin_offset: Duration,
+        query: &SearchQuery,
+    ) -> bool {
+        let max_trip_hours = query.max_trip_hours.unwrap_or(DEFAULT_MAX_TRIP_HOURS);
+        flight.arrive_utc <= date + Duration::hours(max_trip_hours as i64) - origin_offset
+            && query
+                .arrive_before
+                .is_none_or(|deadline| flight.arrive_at <= deadline)
+    }
+
+    // Offset into the day starting at `date` given by an "HH:MM" bound, or
+    // `default` when unset or unparsable.
+    fn time_of_day(
+        date: NaiveDateTime,
+        bound: &Option<String>,
+        default: Duration,
+    ) -> NaiveDateTime {
+        let Some(bound) = bound else {
+            return date + default;
+        };
+        match NaiveTime::parse_from_str(bound, "%H:%M") {
+            Ok(time) => date + (time - NaiveTime::MIN),
+            Err(_) => {
+                tracing::warn!(bound = %bound, "ignoring departure bound not in HH:MM format");
+                date + default
+            }
+        }
+    }
+
+    // Initial states: every admissible first leg out of `sources` departing
+    // within the query's departure window on the day starting at `date`.
+    fn seed(
+        &self,
+        sources: &[Arc<RwLock<Airport>>],
+        date: NaiveDateTime,
+        query: &SearchQuery,
+    ) -> Vec<PathState> {
+        let start = Self::time_of_day(date, &query.depart_after, Duration::zero());
+        let end = Self::time_of_day(date, &query.depart_before, Duration::hours(24));
+        let flights: Vec<Arc<FlightEdge>> = sources
+            .iter()
+            .flat_map(|source| source.read().unwrap().flights_between(start, Some(end)))
+            .filter(|flight| {
+                Self::within_horizon(flight, date, flight.depart_at - flight.depart_utc, query)
+            })
+            .collect();
+        let start_state = |flight: &Arc<FlightEdge>| {
+            PathState::start(flight, self.leg_cost(flight)?).filtered(query)
+        };
+        if !self.parallel {
+            return flights.iter().filter_map(start_state).collect();
+        }
+        // Collecting keeps the sequential order, so ties on the heap break
+        // the same way and the results do not change.
+        let build = || flights.par_iter().filter_map(start_state).collect();
+        match &self.pool {
+            Some(pool) => pool.install(build),
+            None => build(),
+        }
+    }
+
+    fn traverse(
+        &self,
+        seeds: Vec<PathState>,
+        endpoints: &Endpoints,
+        date: NaiveDateTime,
+        total: usize,
+        query: &SearchQuery,
+        view: Option<&SearchView>,
+    ) -> Traversal {
+        self.traverse_with(
+            seeds,
+            endpoints,
+            date,
+            total,
+            query,
+            view,
+            None,
+            &mut |_| true,
+        )
+    }
+
+    // Flights leaving where `arrival` lands from `start` to `end` (UTC),
+    // read under the airport's lock.
+    fn departures(
+        arrival: &FlightEdge,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Vec<ViewFlight> {
+        // The airport indexes departures by local time. Its offset may
+        // change between arrival and departure, so look a little wider and
+        // filter on UTC.
+        let local_offset = arrival.arrive_at - arrival.arrive_utc;
+        let slack = Duration::hours(2);
+        let (from, flights) = {
+            let airport = arrival.to.read().unwrap();
+            let flights = airport.flights_between(
+                start + local_offset - slack,
+                Some(end + local_offset + slack),
+            );
+            (airport.id, flights)
+        };
+        flights
+            .into_iter()
+            .filter(|x| start <= x.depart_utc && x.depart_utc <= end)
+            .map(|flight| {
+                let to = flight.to.read().unwrap().id;
+                ViewFlight { flight, from, to }
+            })
+            .collect()
+    }
+
+    // `traverse_with` for searches that only admit nonstop itineraries.
+    // Every result is then a first leg straight to a target, so the seeds
+    // only need sorting rather than a frontier.
+    fn traverse_direct(
+        seeds: Vec<PathState>,
+        endpoints: &Endpoints,
+        total: usize,
+        query: &SearchQuery,
+        emit: &mut dyn FnMut(&PathState) -> bool,
+    ) -> Traversal {
+        let started = std::time::Instant::now();
+        let generated = seeds.len();
+        let mut direct: Vec<PathState> = seeds
+            .into_iter()
+            .filter(|x| endpoints.targets.contains(&x.current.to.read().unwrap().id))
+            .filter(|x| x.visits_all(&query.via))
+            .collect();
+        direct.sort_by_key(|x| x.rank());
+        let mut results = vec![];
+        for state in direct.into_iter().take(total) {
+            if !emit(&state) {
+                break;
+            }
+            results.push(state);
+        }
+        let stats = SearchStats {
+            generated,
+            expanded: results.len(),
+            heap_peak: 0,
+            dominated: 0,
+            elapsed: started.elapsed(),
+        };
+        tracing::debug!(results_found = results.len(), "nonstop search finished");
+        Traversal {
+            results,
+            stats,
+            ..Default::default()
+        }
+    }
+
+    // `traverse`, handing each result to `emit` as soon as it is found.
+    // The search stops early once `emit` returns false or `cancel` is
+    // cancelled. Connections are looked up in `view` when given, without
+    // locking airports.
+    #[allow(clippy::too_many_arguments)]
+    fn traverse_with(
+        &self,
+        seeds: Vec<PathState>,
+        endpoints: &Endpoints,
+        date: NaiveDateTime,
+        total: usize,
+        query: &SearchQuery,
+        view: Option<&SearchView>,
+        cancel: Option<&CancellationToken>,
+        emit: &mut dyn FnMut(&PathState) -> bool,
+    ) -> Traversal {
+        // A first leg into another origin is never better than starting
+        // there, unless that origin is also a destination.
+        let seeds: Vec<PathState> = seeds
+            .into_iter()
+            .filter(|x| {
+                let to = x.current.to.read().unwrap().id;
+                !endpoints.sources.contains(&to) || endpoints.targets.contains(&to)
+            })
+            .collect();
+        if query.nonstop_only() {
+            return Self::traverse_direct(seeds, endpoints, total, query, emit);
+        }
+        let span = tracing::debug_span!(
+            "traverse",
+            hops = query.hops,
+            nodes_expanded = tracing::field::Empty,
+            heap_pushes = tracing::field::Empty,
+            heap_peak_len = tracing::field::Empty,
+            results_found = tracing::field::Empty,
+            elapsed = tracing::field::Empty,
+        )
+        .entered();
+        let started = std::time::Instant::now();
+        let mut heap_peak = 0;
+        let mut k = query.hops;
+        let mut expanded = 0;
+        let mut next_tightening = query.expansion_budget;
+        let mut degraded = false;
+        let mut cancelled = false;
+        let mut generated = 0;
+        let mut heap = BinaryHeap::new();
+        let mut pending = VecDeque::new();
+        match query.seeding {
+            SeedingStrategy::All => {
+                generated = seeds.len();
+                heap = seeds
+                    .into_iter()
+                    .enumerate()
+                    .map(|(pushed, x)| ByRank(x, pushed))
+                    .collect();
+            }
+            SeedingStrategy::Ordered => {
+                let mut seeds = seeds;
+                seeds.sort_by_key(|x| x.rank());
+                pending = seeds.into();
+            }
+        }
+        // Times the search has continued onward from each airport. No
+        // airport is continued from more than `k` times, so only its `k`
+        // best arrivals get onward connections.
+        let mut count: HashMap<usize, usize> = HashMap::new();
+        // Paths continued onward, by airport and number of legs.
+        let prune = Self::prunes_dominated(query);
+        let mut reached: HashMap<(usize, usize), Vec<Reached>> = HashMap::new();
+        let mut dominated = 0;
+
+        // States are popped best first, so results stay sorted by rank.
+        let mut results = vec![];
+
+        loop {
+            // Ranks never decrease along a path, so a pending first leg can
+            // wait until it ties or beats the best state on the frontier.
+            while let Some(seed) = pending.front() {
+                if heap
+                    .peek()
+                    .is_some_and(|top: &ByRank| top.0.rank() < seed.rank())
+                {
+                    break;
+                }
+                heap.push(ByRank(pending.pop_front().unwrap(), generated));
+                generated += 1;
+            }
+            heap_peak = heap_peak.max(heap.len());
+            if cancel.is_some_and(|token| token.is_cancelled()) {
+                cancelled = true;
+                break;
+            }
+            let Some(ByRank(state, _)) = heap.pop() else {
+                break;
+            };
+            expanded += 1;
+            if let Some(threshold) = next_tightening {
+                if expanded > threshold {
+                    degraded = true;
+                    k /= 2;
+                    if k == 0 {
+                        break;
+                    }
+                    next_tightening = query.expansion_budget.map(|budget| threshold + budget);
+                }
+            }
+
+            let curr = state.current.clone();
+            let node = state.at;
+            let origin_offset = state.origin_offset();
+
+            if endpoints.targets.contains(&node) && state.visits_all(&query.via) {
+                if !emit(&state) {
+                    break;
+                }
+                results.push(state.clone());
+                if results.len() == total {
+                    break;
+                }
+            }
+            let expansions = count.entry(node).or_default();
+            *expansions += 1;
+            if *expansions > k {
+                continue;
+            }
+            if prune && state.penalty == 0 {
+                reached.entry((node, state.path.len())).or_default().push((
+                    state.cost,
+                    curr.arrive_utc,
+                    origin_offset,
+                    state.visited.clone(),
+                ));
+            }
+
+            let min_layover = query
+                .min_layover_minutes
+                .map_or(MIN_CONNECTION_MINUTES, |x| x as i64);
+            // Connection windows are worked out in UTC, so they hold
+            // whatever zones the legs are in.
+            let start_date = curr.arrive_utc + Duration::minutes(min_layover);
+            let window = query
+                .search_window_hours
+                .unwrap_or(DEFAULT_SEARCH_WINDOW_HOURS);
+            let mut end_date = date + Duration::hours(window as i64) - origin_offset;
+            if let Some(max_layover) = query.max_layover_minutes {
+                end_date = end_date.min(curr.arrive_utc + Duration::minutes(max_layover as i64));
+            }
+
+            if start_date > end_date {
+                continue;
+            }
+            let locked;
+            let flights = match view {
+                Some(view) => view.departures(node, start_date, end_date),
+                None => {
+                    locked = Self::departures(&curr, start_date, end_date);
+                    &locked[..]
+                }
+            };
+
+            for ViewFlight {
+                flight: edge, to, ..
+            } in flights
+            {
+                let to = *to;
+                if !Self::within_horizon(edge, date, origin_offset, query) {
+                    continue;
+                }
+                // Never back to an origin or anywhere else already on the
+                // path, so no itinerary flies a cycle.
+                if endpoints.sources.contains(&to) || state.visited.contains(&to) {
+                    continue;
+                }
+
+                if let Some(mut new_state) = self
+                    .leg_cost(edge)
+                    .and_then(|cost| state.extend_to(edge, to, cost))
+                    .and_then(|x| x.filtered(query))
+                {
+                    new_state.completion_probability *=
+                        self.connection_success(curr.flight_id, edge.flight_id);
+                    if prune {
+                        let dominators = reached.get(&(to, new_state.path.len())).map_or(0, |x| {
+                            x.iter()
+                                .filter(|(cost, arrive_utc, offset, visited)| {
+                                    *cost < new_state.cost
+                                        && *arrive_utc < edge.arrive_utc
+                                        && *offset == origin_offset
+                                        && visited.is_subset(&new_state.visited)
+                                })
+                                .count()
+                        });
+                        if dominators >= total {
+                            dominated += 1;
+                            continue;
+                        }
+                    }
+                    heap.push(ByRank(new_state, generated));
+                    generated += 1;
+                }
+            }
+        }
+        let stats = SearchStats {
+            generated,
+            expanded,
+            heap_peak,
+            dominated,
+            elapsed: started.elapsed(),
+        };
+        span.record("nodes_expanded", stats.expanded);
+        span.record("heap_pushes", stats.generated);
+        span.record("heap_peak_len", stats.heap_peak);
+        span.record("results_found", results.len());
+        span.record("elapsed", tracing::field::debug(stats.elapsed));
+        tracing::debug!(degraded, cancelled, "traversal finished");
+        Traversal {
+            results,
+            degraded,
+            cancelled,
+            stats,
+        }
+    }
+}
+
+/// Two states are equal when they fly the same flights in the same order,
+/// whatever they cost or how they are ranked.
 impl PartialEq for PathState {
     fn eq(&self, other: &Self) -> bool {
-        self.cost == other.cost
+        self.path.len() == other.path.len()
+            && self
+                .path
+                .iter()
+                .zip(&other.path)
+                .all(|(a, b)| a.flight_id == b.flight_id)
+    }
+}
+impl Eq for PathState {}
+
+// Orders the search frontier so the best-ranked state is popped first, and
+// of equally ranked states the one pushed first, as numbered by the second
+// field. Kept apart from `PathState`, whose equality is by flights: equally
+// ranked states are still different itineraries.
+struct ByRank(PathState, usize);
+
+impl PartialEq for ByRank {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for ByRank {}
+impl PartialOrd for ByRank {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for ByRank {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .0
+            .rank()
+            .cmp(&self.0.rank())
+            .then(other.1.cmp(&self.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::task::{Context, Poll, Wake, Waker};
+    use tokio_stream::StreamExt;
+
+    use super::*;
+    use crate::currency::StaticRates;
+    use crate::flight::FlightDTO;
+    use crate::testutil::{self, flight, flown_by, graph, ids, query};
+
+    // The same query with dominance pruning turned off. An airline cap
+    // nothing can reach disables it without changing the results.
+    fn unpruned(query: SearchQuery) -> SearchQuery {
+        SearchQuery {
+            max_airlines: Some(usize::MAX),
+            ..query
+        }
+    }
+
+    #[test]
+    fn pruning_keeps_results_and_saves_pushes_on_a_diamond() {
+        // 0 -> {1, 2} -> 3 -> 4, with parallel flights of differing price
+        // and arrival on each side of the diamond.
+        let search = Search::new(graph(
+            5,
+            vec![
+                flight(0, 0, 1, 10, "06:00", "07:00"),
+                flight(1, 0, 1, 22, "06:00", "07:30"),
+                flight(2, 0, 2, 15, "06:00", "07:00"),
+                flight(3, 0, 2, 35, "06:00", "07:30"),
+                flight(4, 1, 3, 10, "08:00", "09:00"),
+                flight(5, 1, 3, 20, "08:30", "09:30"),
+                flight(9, 1, 3, 5, "08:00", "08:45"),
+                flight(6, 2, 3, 10, "08:00", "09:00"),
+                flight(7, 2, 3, 20, "08:30", "09:30"),
+                flight(8, 3, 4, 10, "10:00", "11:00"),
+            ],
+        ));
+        let q = query(0, 4, 5, 2);
+        let (pruned, pruned_stats) = search.find_with_stats(q.clone()).unwrap();
+        let (full, full_stats) = search.find_with_stats(unpruned(q)).unwrap();
+        assert_eq!(ids(&pruned), ids(&full));
+        assert_eq!(ids(&pruned), vec![vec![0, 9, 8], vec![0, 4, 8]]);
+        assert!(pruned_stats.dominated > 0);
+        assert!(pruned_stats.generated < full_stats.generated);
+    }
+
+    #[test]
+    fn pruning_keeps_paths_whose_onward_route_the_dominators_visited() {
+        // 0 -> 1 -> 2 reaches 2 cheaper and earlier than 0 -> 3 -> 2, but
+        // cannot continue 2 -> 1 -> 4 as it has already been to 1.
+        let search = Search::new(graph(
+            5,
+            vec![
+                flight(0, 0, 1, 10, "06:00", "07:00"),
+                flight(1, 1, 2, 10, "08:00", "09:00"),
+                flight(6, 1, 2, 12, "08:00", "09:30"),
+                flight(2, 0, 3, 25, "06:00", "07:00"),
+                flight(3, 3, 2, 50, "08:30", "10:00"),
+                flight(4, 2, 1, 10, "11:00", "12:00"),
+                flight(5, 1, 4, 10, "13:00", "14:00"),
+            ],
+        ));
+        let q = query(0, 4, 5, 2);
+        let pruned = search.find(q.clone()).unwrap();
+        let full = search.find(unpruned(q)).unwrap();
+        assert_eq!(ids(&full), vec![vec![0, 5], vec![2, 3, 4, 5]]);
+        assert_eq!(ids(&pruned), ids(&full));
+    }
+
+    fn cached_search() -> Search {
+        Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")])).with_cache(4)
+    }
+
+    fn cached_entries(search: &Search) -> usize {
+        search.cache.as_ref().unwrap().lock().unwrap().len()
+    }
+
+    // Empties every cached result, so a query served from the cache finds
+    // no route while one searched afresh does.
+    fn hollow_cache(search: &Search) {
+        for (_, found) in search.cache.as_ref().unwrap().lock().unwrap().iter_mut() {
+            found.clear();
+        }
+    }
+
+    #[test]
+    fn cache_serves_repeated_queries() {
+        let search = cached_search();
+        assert_eq!(ids(&search.find(query(0, 1, 1, 5)).unwrap()), vec![vec![0]]);
+        assert_eq!(cached_entries(&search), 1);
+        hollow_cache(&search);
+        assert_eq!(
+            search.find(query(0, 1, 1, 5)).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+        assert_eq!(cached_entries(&search), 1);
+    }
+
+    #[test]
+    fn cache_misses_on_other_queries() {
+        let search = cached_search();
+        search.find(query(0, 1, 1, 5)).unwrap();
+        search.find(query(0, 1, 2, 5)).unwrap();
+        assert_eq!(cached_entries(&search), 2);
+    }
+
+    #[test]
+    fn cache_is_invalidated_by_new_flights() {
+        let search = cached_search();
+        search.find(query(0, 1, 1, 5)).unwrap();
+        search
+            .airports
+            .read()
+            .unwrap()
+            .add_flight(flight(1, 0, 1, 50, "10:00", "11:00"));
+        let found = search.find(query(0, 1, 1, 5)).unwrap();
+        assert_eq!(ids(&found), vec![vec![1], vec![0]]);
+    }
+
+    #[test]
+    fn cache_keys_ignore_set_order() {
+        let search = cached_search();
+        for ids in [(100..200).collect::<Vec<_>>(), (100..200).rev().collect()] {
+            let query = SearchQuery {
+                exclude_flights: Some(ids.iter().copied().collect()),
+                exclude_airports: Some(ids.into_iter().collect()),
+                ..query(0, 1, 1, 5)
+            };
+            search.find(query).unwrap();
+        }
+        assert_eq!(cached_entries(&search), 1);
+    }
+
+    fn one_permit_search() -> Search {
+        Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")]))
+            .with_concurrency(NonZeroUsize::MIN)
+    }
+
+    async fn take_permit(search: &Search) -> OwnedSemaphorePermit {
+        let permits = search.permits.clone().unwrap();
+        permits.acquire_owned().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn async_searches_queue_for_a_permit() {
+        let search = one_permit_search();
+        let held = take_permit(&search).await;
+        let queued = search.find_async(query(0, 1, 1, 5));
+        tokio::pin!(queued);
+        let wait = std::time::Duration::from_millis(50);
+        assert!(time::timeout(wait, &mut queued).await.is_err());
+        drop(held);
+        assert_eq!(ids(&queued.await.unwrap()), vec![vec![0]]);
+    }
+
+    #[tokio::test]
+    async fn async_searches_time_out_waiting_for_a_permit() {
+        let search = one_permit_search().with_queue_timeout(std::time::Duration::from_millis(20));
+        let _held = take_permit(&search).await;
+        assert_eq!(
+            search.find_async(query(0, 1, 1, 5)).await.unwrap_err(),
+            SearchError::Overloaded
+        );
+    }
+
+    // Airports `0..airports` joined by `flights` random flights, with few
+    // distinct fares so that many paths tie on cost.
+    fn random_graph(seed: u64, airports: usize, flights: usize) -> AirportsContainer {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let flights = (0..flights)
+            .map(|id| {
+                let from = rng.gen_range(0..airports);
+                let to = (from + rng.gen_range(1..airports)) % airports;
+                let depart = rng.gen_range(0..20);
+                let arrive = depart + rng.gen_range(1..4);
+                flight(
+                    id,
+                    from,
+                    to,
+                    rng.gen_range(1..4) * 10,
+                    &format!("{:02}:00", depart),
+                    &format!("{:02}:30", arrive),
+                )
+            })
+            .collect();
+        graph(airports, flights)
+    }
+
+    #[test]
+    fn find_cheapest_matches_find_with_one_result() {
+        for seed in 0..20 {
+            let search = Search::new(random_graph(seed, 8, 80));
+            for from in 0..8 {
+                for to in (0..8).filter(|&x| x != from) {
+                    for hops in 1..4 {
+                        let best = search.find_or_empty(query(from, to, hops, 1));
+                        let cheapest = search.find_cheapest(from, to, testutil::DATE, hops);
+                        assert_eq!(
+                            ids(&best),
+                            ids(&Vec::from_iter(cheapest)),
+                            "seed {} from {} to {} hops {}",
+                            seed,
+                            from,
+                            to,
+                            hops
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn best_value_picks_a_balanced_itinerary() {
+        // Cheapest but slow, fastest but dear, and a little of both.
+        let search = Search::new(graph(
+            2,
+            vec![
+                flight(0, 0, 1, 100, "06:00", "16:00"),
+                flight(1, 0, 1, 300, "08:00", "09:00"),
+                flight(2, 0, 1, 150, "08:00", "10:00"),
+            ],
+        ));
+        let query = SearchQuery {
+            objective: Objective::BestValue,
+            ..query(0, 1, 1, 1)
+        };
+        assert_eq!(ids(&search.find(query).unwrap()), vec![vec![2]]);
     }
-}
-impl Eq for PathState {}
-impl PartialOrd for PathState {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        other.cost.partial_cmp(&self.cost)
+
+    // Counts the searches converting a fare at once, holding each one long
+    // enough for the others to catch up.
+    #[derive(Default)]
+    struct Overlap {
+        running: AtomicUsize,
+        most: AtomicUsize,
     }
-}
-impl Ord for PathState {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.cost.cmp(&self.cost)
+
+    impl CurrencyConverter for Overlap {
+        fn to_base(&self, amount: i64, _from: &str) -> Option<i64> {
+            let running = self.running.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+            self.most.fetch_max(running, AtomicOrdering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            self.running.fetch_sub(1, AtomicOrdering::SeqCst);
+            Some(amount)
+        }
+
+        fn base_to(&self, amount: i64, _to: &str) -> Option<i64> {
+            Some(amount)
+        }
+    }
+
+    // Fails every search that converts a fare.
+    struct Broken;
+
+    impl CurrencyConverter for Broken {
+        fn to_base(&self, _amount: i64, _from: &str) -> Option<i64> {
+            panic!("no rates");
+        }
+
+        fn base_to(&self, _amount: i64, _to: &str) -> Option<i64> {
+            panic!("no rates");
+        }
+    }
+
+    #[tokio::test]
+    async fn searches_lost_in_the_background_are_internal_errors() {
+        let search = Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")]))
+            .with_currency_converter(Arc::new(Broken));
+        assert_eq!(
+            search.find_async(query(0, 1, 1, 1)).await.unwrap_err(),
+            SearchError::Internal
+        );
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .panic_handler(|_| {})
+            .build()
+            .unwrap();
+        let pooled = search.with_pool(pool);
+        assert_eq!(
+            pooled.find_async(query(0, 1, 1, 1)).await.unwrap_err(),
+            SearchError::Internal
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pooled_searches_run_at_most_the_pool_size_at_once() {
+        let overlap = Arc::new(Overlap::default());
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let search = Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")]))
+            .with_currency_converter(overlap.clone())
+            .with_pool(pool);
+        let searches: Vec<_> = (0..8)
+            .map(|_| {
+                let search = search.clone();
+                tokio::spawn(async move { search.find_async(query(0, 1, 1, 1)).await })
+            })
+            .collect();
+        for handle in searches {
+            assert_eq!(ids(&handle.await.unwrap().unwrap()), vec![vec![0]]);
+        }
+        assert_eq!(overlap.most.load(AtomicOrdering::SeqCst), 2);
+    }
+
+    #[test]
+    fn itineraries_report_emissions_only_with_a_factor() {
+        let flights = vec![
+            flight(0, 0, 1, 100, "08:00", "09:00"),
+            flight(1, 1, 2, 100, "10:00", "11:00"),
+        ];
+        // Per-leg estimates, then the total.
+        let emissions = |search: &Search| {
+            let found = search.find_itineraries(query(0, 2, 2, 1)).unwrap();
+            let itinerary = &found[0];
+            let legs: Vec<_> = itinerary
+                .legs
+                .iter()
+                .map(|x| x.estimated_emissions_kg)
+                .collect();
+            (legs, itinerary.estimated_emissions_kg)
+        };
+        let search = Search::new(graph(3, flights));
+        assert_eq!(emissions(&search), (vec![None, None], None));
+        let search = search.with_emission_factor(0.5);
+        assert_eq!(
+            emissions(&search),
+            (vec![Some(50.0), Some(50.0)], Some(100.0))
+        );
+    }
+
+    #[test]
+    fn max_layover_ranks_a_stopover_above_a_quick_connection() {
+        // The same fare through 1 with an hour to connect, or through 2
+        // with six.
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 3, 100, "10:00", "11:00"),
+                flight(2, 0, 2, 100, "08:00", "09:00"),
+                flight(3, 2, 3, 100, "15:00", "16:00"),
+            ],
+        ));
+        let query = SearchQuery {
+            objective: Objective::MaxLayover,
+            ..query(0, 3, 2, 2)
+        };
+        assert_eq!(
+            ids(&search.find(query).unwrap()),
+            vec![vec![2, 3], vec![0, 1]]
+        );
+    }
+
+    fn at(time: &str) -> NaiveDateTime {
+        let time = format!("{} {}:00", testutil::DATE, time);
+        NaiveDateTime::parse_from_str(&time, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn arrive_before_drops_late_itineraries_and_ranks_the_rest_by_cost() {
+        let search = Search::new(graph(
+            2,
+            vec![
+                flight(0, 0, 1, 100, "15:00", "18:00"),
+                flight(1, 0, 1, 300, "07:00", "10:00"),
+                flight(2, 0, 1, 200, "08:00", "11:00"),
+            ],
+        ));
+        let query = SearchQuery {
+            arrive_before: Some(at("12:00")),
+            ..query(0, 1, 1, 5)
+        };
+        assert_eq!(ids(&search.find(query).unwrap()), vec![vec![2], vec![1]]);
+    }
+
+    #[test]
+    fn completion_probability_multiplies_connection_success() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+                flight(2, 2, 3, 100, "12:00", "13:00"),
+            ],
+        ))
+        .with_misconnect_rates(HashMap::from([((0, 1), 0.1), ((1, 2), 0.2)]));
+        let found = search.find(query(0, 3, 3, 1)).unwrap();
+        assert_eq!(ids(&found), vec![vec![0, 1, 2]]);
+        assert!((found[0].completion_probability() - 0.9 * 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn refine_narrows_the_candidates_without_searching_again() {
+        let search = Search::new(graph(
+            2,
+            vec![
+                flown_by("AA", flight(0, 0, 1, 100, "08:00", "09:00")),
+                flown_by("BA", flight(1, 0, 1, 150, "08:00", "09:00")),
+                flown_by("AA", flight(2, 0, 1, 300, "10:00", "11:00")),
+                flown_by("BA", flight(3, 0, 1, 400, "10:00", "11:00")),
+            ],
+        ));
+        let broad = search.find_result_set(query(0, 1, 1, 2));
+        assert_eq!(ids(&broad.results), vec![vec![0], vec![1]]);
+
+        let ba = FilterSet::default().hard(Constraint::Airlines(vec!["BA".to_string()]));
+        assert_eq!(ids(&broad.refine(&ba)), vec![vec![1], vec![3]]);
+        let cheap = FilterSet::default().hard(Constraint::MaxPrice(350));
+        assert_eq!(ids(&broad.refine(&cheap)), vec![vec![0], vec![1]]);
+        let aa_cheap = FilterSet::default()
+            .hard(Constraint::Airlines(vec!["AA".to_string()]))
+            .hard(Constraint::MaxPrice(350));
+        assert_eq!(ids(&broad.refine(&aa_cheap)), vec![vec![0], vec![2]]);
+    }
+
+    #[test]
+    fn continuations_all_start_with_the_pinned_flight() {
+        // Cheaper ways out of 0 than the pinned flight 0 are ignored.
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 200, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+                flight(2, 1, 2, 150, "12:00", "13:00"),
+                flight(3, 0, 1, 50, "07:00", "08:00"),
+                flight(4, 0, 2, 60, "08:00", "10:00"),
+            ],
+        ));
+        let found = search.find_continuations(0, 2, 3, 5);
+        assert_eq!(ids(&found), vec![vec![0, 1], vec![0, 2]]);
+    }
+
+    #[test]
+    fn departures_board_merges_origins_in_departure_order() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 2, 100, "08:30", "09:30"),
+                flight(1, 1, 2, 100, "08:00", "09:00"),
+                flight(2, 0, 2, 100, "10:00", "11:00"),
+                flight(3, 1, 2, 100, "11:30", "12:30"),
+                flight(4, 2, 0, 100, "09:00", "10:00"),
+                flight(5, 0, 1, 100, "07:00", "08:00"),
+            ],
+        ));
+        let board = search.departures_board(&[0, 1], at("08:00"), Duration::hours(3));
+        let flights: Vec<usize> = board.iter().map(|x| x.flight_id).collect();
+        assert_eq!(flights, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn expansion_budget_degrades_instead_of_running_on() {
+        let search = Search::new(random_graph(1, 10, 400));
+        let dense = unpruned(query(0, 9, 64, 1000));
+        let full = search.find_result_set(dense.clone());
+        assert!(!full.degraded);
+
+        let budget = 200;
+        let query = SearchQuery {
+            expansion_budget: Some(budget),
+            ..dense
+        };
+        let bounded = search.find_result_set(query);
+        assert!(bounded.degraded);
+        assert!(!bounded.results.is_empty());
+        // Halving a cap of 64 reaches zero one state into the eighth
+        // budget.
+        assert!(bounded.stats.expanded <= budget * 7 + 1);
+        assert!(bounded.stats.expanded < full.stats.expanded);
+    }
+
+    // `flight` moved to the day after `testutil::DATE`.
+    fn next_day(flight: FlightDTO) -> FlightDTO {
+        let move_date = |date: &str| date.replace(testutil::DATE, "2024-01-15");
+        FlightDTO {
+            departure_date: move_date(&flight.departure_date),
+            arrival_date: move_date(&flight.arrival_date),
+            ..flight
+        }
+    }
+
+    #[test]
+    fn date_flex_weighs_cheaper_days_against_the_requested_one() {
+        let search = Search::new(graph(
+            2,
+            vec![
+                flight(0, 0, 1, 500, "08:00", "09:00"),
+                next_day(flight(1, 0, 1, 100, "08:00", "09:00")),
+            ],
+        ));
+        let flexible = |penalty| SearchQuery {
+            date_flex_days: 1,
+            date_flex_penalty: Some(penalty),
+            ..query(0, 1, 1, 5)
+        };
+
+        let found = search.find(flexible(100)).unwrap();
+        assert_eq!(ids(&found), vec![vec![1], vec![0]]);
+        let requested = at("08:00").date();
+        let departures: Vec<_> = found.iter().map(|x| x.departure_date()).collect();
+        assert_eq!(departures, vec![requested.succ_opt().unwrap(), requested]);
+        // A day's move is now worth more than the saving.
+        assert_eq!(
+            ids(&search.find(flexible(1000)).unwrap()),
+            vec![vec![0], vec![1]]
+        );
+        assert_eq!(ids(&search.find(query(0, 1, 1, 5)).unwrap()), vec![vec![0]]);
+    }
+
+    #[test]
+    fn date_flex_prefers_the_requested_day_by_default() {
+        let saving = DEFAULT_DATE_FLEX_PENALTY as i64 / 2;
+        let search = Search::new(graph(
+            2,
+            vec![
+                flight(0, 0, 1, 500, "08:00", "09:00"),
+                next_day(flight(1, 0, 1, 500 - saving, "08:00", "09:00")),
+            ],
+        ));
+        let flexible = |penalty| SearchQuery {
+            date_flex_days: 1,
+            date_flex_penalty: penalty,
+            ..query(0, 1, 1, 5)
+        };
+        assert_eq!(
+            ids(&search.find(flexible(None)).unwrap()),
+            vec![vec![0], vec![1]]
+        );
+        assert_eq!(
+            ids(&search.find(flexible(Some(0))).unwrap()),
+            vec![vec![1], vec![0]]
+        );
+        // Added to a soft constraint's penalty, the day's penalty is too
+        // large to sum, but still ranks the other day last.
+        let found = search
+            .find(SearchQuery {
+                filters: FilterSet::default().soft(Constraint::MaxPrice(100), 10),
+                ..flexible(Some(i32::MAX))
+            })
+            .unwrap();
+        assert_eq!(ids(&found), vec![vec![0], vec![1]]);
+        assert_eq!(found[1].penalty, i32::MAX);
+    }
+
+    #[test]
+    fn no_carrier_changes_keeps_to_one_airline() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flown_by("AA", flight(0, 0, 1, 100, "08:00", "09:00")),
+                flown_by("AA", flight(1, 1, 2, 200, "10:00", "11:00")),
+                flown_by("BA", flight(2, 1, 2, 100, "10:00", "11:00")),
+            ],
+        ));
+        let any = query(0, 2, 3, 5);
+        assert_eq!(
+            ids(&search.find(any.clone()).unwrap()),
+            vec![vec![0, 2], vec![0, 1]]
+        );
+        let single_carrier = SearchQuery {
+            max_carrier_changes: Some(0),
+            ..any
+        };
+        assert_eq!(ids(&search.find(single_carrier).unwrap()), vec![vec![0, 1]]);
+    }
+
+    #[test]
+    fn ordered_seeding_does_less_work_for_the_same_earliest_arrival() {
+        // A first leg every hour, and one connection onward from the first.
+        let mut flights: Vec<_> = (6..20)
+            .map(|hour| {
+                let depart = format!("{:02}:00", hour);
+                let arrive = format!("{:02}:30", hour);
+                flight(hour, 0, 1, 100, &depart, &arrive)
+            })
+            .collect();
+        flights.push(flight(0, 1, 2, 100, "08:00", "09:00"));
+        let search = Search::new(graph(3, flights));
+        let seeded = |seeding| {
+            let query = SearchQuery {
+                objective: Objective::EarliestArrival,
+                seeding,
+                ..query(0, 2, 3, 1)
+            };
+            search.find_with_stats(query).unwrap()
+        };
+
+        let (all, all_stats) = seeded(SeedingStrategy::All);
+        let (ordered, ordered_stats) = seeded(SeedingStrategy::Ordered);
+        assert_eq!(ids(&ordered), ids(&all));
+        assert_eq!(ids(&ordered), vec![vec![6, 0]]);
+        assert!(ordered_stats.generated < all_stats.generated);
+        assert!(ordered_stats.expanded <= all_stats.expanded);
+    }
+
+    #[test]
+    fn groups_need_a_seat_each_on_every_leg() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                FlightDTO {
+                    seats: 1,
+                    ..flight(0, 0, 1, 100, "08:00", "09:00")
+                },
+                flight(1, 0, 2, 100, "08:00", "09:00"),
+                flight(2, 2, 1, 100, "10:00", "11:00"),
+            ],
+        ));
+        assert_eq!(ids(&search.find(query(0, 1, 3, 1)).unwrap()), vec![vec![0]]);
+        let pair = SearchQuery {
+            passengers: 2,
+            ..query(0, 1, 3, 5)
+        };
+        let itineraries = search.find_itineraries(pair).unwrap();
+        let flights: Vec<Vec<usize>> = itineraries
+            .iter()
+            .map(|x| x.legs.iter().map(|leg| leg.flight_id).collect())
+            .collect();
+        assert_eq!(flights, vec![vec![1, 2]]);
+        assert_eq!(itineraries[0].total_cost, 400);
+    }
+
+    #[test]
+    fn tie_break_seeds_shuffle_equal_costs_reproducibly() {
+        // Eight flights at one price, and a dearer one that stays last.
+        let mut flights: Vec<_> = (0..8)
+            .map(|id| {
+                let depart = format!("{:02}:00", 8 + id);
+                let arrive = format!("{:02}:30", 8 + id);
+                flight(id, 0, 1, 100, &depart, &arrive)
+            })
+            .collect();
+        flights.push(flight(8, 0, 1, 200, "07:00", "08:00"));
+        let search = Search::new(graph(2, flights));
+        let seeded = |seed| {
+            let query = SearchQuery {
+                tie_break_seed: seed,
+                ..query(0, 1, 1, 9)
+            };
+            ids(&search.find(query).unwrap())
+        };
+
+        let unseeded = seeded(None);
+        assert_eq!(seeded(Some(1)), seeded(Some(1)));
+        assert_ne!(seeded(Some(1)), seeded(Some(2)));
+        assert_ne!(seeded(Some(1)), unseeded);
+        let mut shuffled = seeded(Some(1));
+        assert_eq!(shuffled.pop(), Some(vec![8]));
+        shuffled.sort();
+        assert_eq!(shuffled, unseeded[..8]);
+    }
+
+    #[test]
+    fn cheapest_anywhere_finds_the_cheapest_way_to_each_airport() {
+        // 2 is cheaper through 1 than direct; 4 is only served before
+        // anyone reaches 2.
+        let search = Search::new(graph(
+            5,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 0, 2, 300, "08:00", "09:00"),
+                flight(2, 1, 2, 50, "10:00", "11:00"),
+                flight(3, 0, 3, 80, "08:00", "09:00"),
+                flight(4, 2, 4, 10, "08:30", "09:30"),
+            ],
+        ));
+        let found = search.cheapest_anywhere(0, testutil::DATE, 2, 5);
+        assert_eq!(ids(&found), vec![vec![3], vec![0], vec![0, 2]]);
+        let costs: Vec<i64> = found.iter().map(|x| x.cost()).collect();
+        assert_eq!(costs, vec![80, 100, 150]);
+        let top = search.cheapest_anywhere(0, testutil::DATE, 2, 2);
+        assert_eq!(ids(&top), vec![vec![3], vec![0]]);
+        let nonstop = search.cheapest_anywhere(0, testutil::DATE, 1, 5);
+        assert_eq!(ids(&nonstop), vec![vec![3], vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn dumped_caches_reload_into_a_fresh_search() {
+        let search = cached_search();
+        search.find(query(0, 1, 1, 5)).unwrap();
+        let path = testutil::temp_file("dumped.cache", "");
+        let path = path.to_str().unwrap();
+        search.dump_cache(path).unwrap();
+
+        let restarted = cached_search();
+        assert_eq!(restarted.load_cache(path).unwrap(), 1);
+        assert_eq!(cached_entries(&restarted), 1);
+        hollow_cache(&restarted);
+        assert_eq!(
+            restarted.find(query(0, 1, 1, 5)).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+    }
+
+    #[test]
+    fn cache_entries_outside_the_graph_dates_are_not_reloaded() {
+        let search = cached_search();
+        let elsewhen = SearchQuery {
+            date: "2024-02-01".to_string(),
+            ..query(0, 1, 1, 5)
+        };
+        search.find_or_empty(elsewhen);
+        let path = testutil::temp_file("stale.cache", "");
+        let path = path.to_str().unwrap();
+        search.dump_cache(path).unwrap();
+        let dumped = std::fs::read_to_string(path).unwrap();
+        assert!(dumped.contains("2024-02-01"));
+
+        assert_eq!(cached_search().load_cache(path).unwrap(), 0);
+    }
+
+    #[test]
+    fn preferences_pick_different_top_results() {
+        // A cheap connection and a dear nonstop.
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "12:00", "13:00"),
+                flight(2, 0, 2, 400, "08:00", "10:00"),
+            ],
+        ));
+        let top = |preferences| {
+            let query = SearchQuery {
+                objective: Objective::Personalized,
+                preferences,
+                ..query(0, 2, 3, 1)
+            };
+            ids(&search.find(query).unwrap())
+        };
+        assert_eq!(top(Preferences::default()), vec![vec![0, 1]]);
+        let in_a_hurry = Preferences {
+            price: 1.0,
+            duration: 2.0,
+            stops: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(top(in_a_hurry), vec![vec![2]]);
+    }
+
+    // Polls `future` to completion on this thread, with no tokio runtime
+    // around it.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn find_async_runs_outside_a_runtime() {
+        assert!(Handle::try_current().is_err());
+        let search = Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")]));
+        let found = block_on(search.find_async(query(0, 1, 1, 5))).unwrap();
+        assert_eq!(ids(&found), vec![vec![0]]);
+        assert_eq!(
+            block_on(search.find_async(query(1, 0, 1, 5))).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+    }
+
+    #[test]
+    fn force_first_pins_one_of_several_origins() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 2, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 50, "08:00", "09:00"),
+            ],
+        ));
+        let from_either = SearchQuery {
+            origins: Some(vec![0, 1]),
+            ..query(0, 2, 1, 5)
+        };
+        let forced = |first| SearchQuery {
+            force_first: Some(first),
+            ..from_either.clone()
+        };
+        assert_eq!(
+            ids(&search.find(from_either.clone()).unwrap()),
+            vec![vec![1], vec![0]]
+        );
+        assert_eq!(ids(&search.find(forced(0)).unwrap()), vec![vec![0]]);
+        assert_eq!(
+            search.find(forced(2)).unwrap_err(),
+            SearchError::UnknownOrigin
+        );
+    }
+
+    // Cheap and slow (0), fast and dear (1), and dearer and slower than
+    // the fast one (2).
+    fn trade_offs() -> Search {
+        Search::new(graph(
+            2,
+            vec![
+                flight(0, 0, 1, 100, "06:00", "14:00"),
+                flight(1, 0, 1, 300, "08:00", "09:00"),
+                flight(2, 0, 1, 350, "07:00", "12:00"),
+            ],
+        ))
+    }
+
+    #[test]
+    fn pareto_frontier_leaves_out_dominated_options() {
+        let frontier = trade_offs().pareto_frontier(query(0, 1, 1, 3));
+        assert_eq!(ids(&frontier), vec![vec![0], vec![1]]);
+    }
+
+    fn departing(after: &str, before: &str) -> SearchQuery {
+        SearchQuery {
+            depart_after: Some(after.to_string()),
+            depart_before: Some(before.to_string()),
+            ..query(0, 2, 3, 5)
+        }
+    }
+
+    #[test]
+    fn depart_window_limits_only_the_first_leg() {
+        // The connection at 13:00 is outside every window below.
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 0, 2, 300, "10:00", "12:00"),
+                flight(2, 1, 2, 100, "13:00", "14:00"),
+            ],
+        ));
+        assert_eq!(
+            ids(&search.find_or_empty(query(0, 2, 3, 5))),
+            vec![vec![0, 2], vec![1]]
+        );
+        assert!(search.find_or_empty(departing("11:00", "12:00")).is_empty());
+        assert_eq!(
+            ids(&search.find_or_empty(departing("07:00", "09:00"))),
+            vec![vec![0, 2]]
+        );
+    }
+
+    #[test]
+    fn durations_span_midnight() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "22:00", "23:30"),
+                next_day(flight(1, 1, 2, 100, "01:00", "02:40")),
+            ],
+        ));
+        // Connections past midnight need a window longer than the day.
+        let overnight = SearchQuery {
+            search_window_hours: Some(36),
+            ..query(0, 2, 3, 1)
+        };
+        let found = search.find(overnight).unwrap();
+        assert_eq!(found[0].layover_durations(), vec![Duration::minutes(90)]);
+        assert_eq!(found[0].total_duration(), Duration::minutes(280));
+        let lines = found[0].readable_path();
+        assert_eq!(lines.last().unwrap(), "Total duration: 4h 40m");
+    }
+
+    #[test]
+    fn cheapest_and_fastest_routes_can_differ() {
+        let search = trade_offs();
+        let ranked_by = |objective| {
+            let query = SearchQuery {
+                objective,
+                ..query(0, 1, 1, 1)
+            };
+            ids(&search.find(query).unwrap())
+        };
+        assert_eq!(ranked_by(Objective::Cost), vec![vec![0]]);
+        assert_eq!(ranked_by(Objective::Duration), vec![vec![1]]);
+    }
+
+    #[test]
+    fn hops_prefers_fewer_legs_then_cost() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 50, "08:00", "09:00"),
+                flight(1, 1, 2, 50, "10:00", "11:00"),
+                flight(2, 0, 2, 300, "08:00", "10:00"),
+                flight(3, 0, 2, 200, "12:00", "14:00"),
+            ],
+        ));
+        let fewest_legs = SearchQuery {
+            objective: Objective::Hops,
+            ..query(0, 2, 3, 5)
+        };
+        assert_eq!(
+            ids(&search.find(fewest_legs).unwrap()),
+            vec![vec![3], vec![2], vec![0, 1]]
+        );
+    }
+
+    #[test]
+    fn max_layover_drops_long_connections() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 50, "08:00", "09:00"),
+                flight(1, 1, 2, 50, "18:00", "19:00"),
+                flight(2, 0, 2, 300, "08:00", "10:00"),
+            ],
+        ));
+        let any = query(0, 2, 3, 5);
+        assert_eq!(
+            ids(&search.find(any.clone()).unwrap()),
+            vec![vec![0, 1], vec![2]]
+        );
+        let short_connections = SearchQuery {
+            max_layover_minutes: Some(120),
+            ..any
+        };
+        assert_eq!(ids(&search.find(short_connections).unwrap()), vec![vec![2]]);
+    }
+
+    #[test]
+    fn min_layover_drops_tight_connections() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 50, "08:00", "09:00"),
+                flight(1, 1, 2, 50, "09:30", "10:30"),
+                flight(2, 0, 2, 300, "08:00", "10:00"),
+            ],
+        ));
+        let any = query(0, 2, 3, 5);
+        assert_eq!(
+            ids(&search.find(any.clone()).unwrap()),
+            vec![vec![0, 1], vec![2]]
+        );
+        let long_connections = SearchQuery {
+            min_layover_minutes: Some(45),
+            ..any
+        };
+        assert_eq!(ids(&search.find(long_connections).unwrap()), vec![vec![2]]);
+    }
+
+    #[test]
+    fn round_trips_return_after_the_minimum_stay() {
+        // Same-day returns at 11:00 (too soon) and 15:00 and 20:00.
+        let search = Search::new(graph(
+            2,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 0, 10, "11:00", "12:00"),
+                flight(2, 1, 0, 80, "15:00", "16:00"),
+                flight(3, 1, 0, 60, "20:00", "21:00"),
+            ],
+        ));
+        let pairs = search.find_round_trip(query(0, 1, 1, 5), testutil::DATE.to_string(), 4);
+        let flights: Vec<(usize, usize)> = pairs
+            .iter()
+            .map(|(out, back)| (out.current.flight_id, back.current.flight_id))
+            .collect();
+        assert_eq!(flights, vec![(0, 3), (0, 2)]);
+
+        let one = search.find_round_trip(query(0, 1, 1, 1), testutil::DATE.to_string(), 4);
+        assert_eq!(one.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn streams_yield_what_find_returns() {
+        let search = Search::new(random_graph(3, 8, 80));
+        for to in 1..8 {
+            let query = query(0, to, 3, 10);
+            let streamed: Vec<PathState> = search.find_stream(query.clone()).collect().await;
+            let found = search.find_or_empty(query);
+            assert_eq!(ids(&streamed), ids(&found), "to {}", to);
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_searches_stop_before_expanding() {
+        let search = Search::new(random_graph(1, 10, 400)).with_cache(4);
+        let dense = unpruned(query(0, 9, 64, 1000));
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let started = std::time::Instant::now();
+        let found = search.find_async_cancellable(dense.clone(), token.clone());
+        assert_eq!(found.await.unwrap_err(), SearchError::NoRouteFound);
+        assert_eq!(
+            search.find_cancellable(dense, &token).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        // Cut-short results are not cached.
+        assert_eq!(cached_entries(&search), 0);
+    }
+
+    #[test]
+    fn each_airport_is_continued_from_at_most_hops_times() {
+        // Five ways into 1, one way on; flight ids far beyond the airport
+        // count.
+        let mut flights: Vec<_> = (0..5)
+            .map(|x| flight(1000 + x, 0, 1, 100 + x as i64, "08:00", "09:00"))
+            .collect();
+        flights.push(flight(2000, 1, 2, 100, "10:00", "11:00"));
+        let search = Search::new(graph(3, flights));
+        for hops in 2..=6 {
+            let found = search.find_or_empty(unpruned(query(0, 2, hops, 10)));
+            assert_eq!(found.len(), hops.min(5), "hops {}", hops);
+        }
+        // A single hop only searches nonstop flights, and there are none.
+        assert!(search.find_or_empty(query(0, 2, 1, 10)).is_empty());
+    }
+
+    #[test]
+    fn costs_past_i32_add_up_and_overflowing_paths_are_skipped() {
+        let leg = i32::MAX as i64;
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, leg, "08:00", "09:00"),
+                flight(1, 1, 2, leg, "10:00", "11:00"),
+                flight(2, 0, 1, i64::MAX, "08:00", "09:00"),
+            ],
+        ));
+        let found = search.find(query(0, 2, 3, 5)).unwrap();
+        assert_eq!(ids(&found), vec![vec![0, 1]]);
+        assert_eq!(found[0].cost(), 2 * leg);
+    }
+
+    #[test]
+    fn readable_paths_name_the_airline() {
+        let search = Search::new(graph(
+            2,
+            vec![FlightDTO {
+                flight_number: "100".to_string(),
+                ..flown_by("AA", flight(0, 0, 1, 100, "08:00", "09:00"))
+            }],
+        ));
+        let found = search.find(query(0, 1, 1, 1)).unwrap();
+        assert_eq!(
+            found[0].readable_path()[0],
+            "Flight 0 (AA100) from A0 to A1, from 2024-01-14 08:00:00 UTC to 2024-01-14 09:00:00 UTC"
+        );
+    }
+
+    fn airlines(codes: &[&str]) -> Option<Vec<String>> {
+        Some(codes.iter().map(|x| x.to_string()).collect())
+    }
+
+    // Two airlines into 1 and two on to 2, making four itineraries from
+    // 160 to 250.
+    fn two_by_two() -> Search {
+        Search::new(graph(
+            3,
+            vec![
+                flown_by("AA", flight(0, 0, 1, 100, "08:00", "09:00")),
+                flown_by("BA", flight(1, 0, 1, 150, "08:00", "09:00")),
+                flown_by("AA", flight(2, 1, 2, 100, "10:00", "11:00")),
+                flown_by("UA", flight(3, 1, 2, 60, "10:00", "11:00")),
+            ],
+        ))
+    }
+
+    #[test]
+    fn airline_lists_apply_to_every_leg() {
+        let search = two_by_two();
+        let any = query(0, 2, 3, 5);
+        assert_eq!(
+            ids(&search.find_or_empty(any.clone())),
+            vec![vec![0, 3], vec![0, 2], vec![1, 3], vec![1, 2]]
+        );
+        let only = |codes| SearchQuery {
+            include_airlines: airlines(codes),
+            ..any.clone()
+        };
+        assert_eq!(
+            ids(&search.find_or_empty(only(&["AA", "BA"]))),
+            vec![vec![0, 2], vec![1, 2]]
+        );
+        assert_eq!(ids(&search.find_or_empty(only(&["AA"]))), vec![vec![0, 2]]);
+        let avoiding_aa = SearchQuery {
+            exclude_airlines: airlines(&["AA"]),
+            ..any.clone()
+        };
+        assert_eq!(ids(&search.find_or_empty(avoiding_aa)), vec![vec![1, 3]]);
+    }
+
+    #[test]
+    fn max_cost_keeps_only_affordable_itineraries() {
+        let search = two_by_two();
+        let budget = |max_cost| SearchQuery {
+            max_cost,
+            ..query(0, 2, 3, 5)
+        };
+        let (all, all_stats) = search.find_with_stats(budget(None)).unwrap();
+        let (affordable, stats) = search.find_with_stats(budget(Some(205))).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(ids(&affordable), vec![vec![0, 3], vec![0, 2]]);
+        assert!(stats.generated < all_stats.generated);
+        // No first leg fits a budget of 90.
+        assert!(search.find_or_empty(budget(Some(90))).is_empty());
+    }
+
+    #[test]
+    fn identical_paths_are_returned_once() {
+        // Midnight is the end of one flexible day and the start of the next,
+        // so both find this flight.
+        let search = Search::new(graph(
+            2,
+            vec![next_day(flight(0, 0, 1, 100, "00:00", "01:00"))],
+        ));
+        let flexible = SearchQuery {
+            date_flex_days: 1,
+            ..query(0, 1, 1, 5)
+        };
+        assert_eq!(ids(&search.find(flexible.clone()).unwrap()), vec![vec![0]]);
+        let duplicates = SearchQuery {
+            allow_duplicates: true,
+            ..flexible
+        };
+        assert_eq!(
+            ids(&search.find(duplicates).unwrap()),
+            vec![vec![0], vec![0]]
+        );
+    }
+
+    #[test]
+    fn equal_cost_paths_over_different_flights_are_not_equal() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 3, 100, "10:00", "11:00"),
+                flight(2, 0, 2, 100, "08:00", "09:00"),
+                flight(3, 2, 3, 100, "10:00", "11:00"),
+            ],
+        ));
+        let found = search.find(query(0, 3, 2, 5)).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].cost, found[1].cost);
+        assert_ne!(found[0], found[1]);
+        assert_eq!(found[0], found[0].clone());
+    }
+
+    #[test]
+    fn parallel_seeding_matches_sequential_on_a_fan_out() {
+        // A hub with hundreds of first legs to 20 spokes, each flying on to 21.
+        let spokes = 20;
+        let mut flights: Vec<FlightDTO> = (0..400)
+            .map(|id| {
+                let depart = id % 12;
+                let time = |hour| format!("{:02}:{:02}", hour, id % 60);
+                let cost = 50 + (id as i64 * 7) % 90;
+                let (departs, arrives) = (time(depart), time(depart + 1));
+                flight(id, 0, 1 + id % spokes, cost, &departs, &arrives)
+            })
+            .collect();
+        flights.extend((0..spokes).map(|spoke| {
+            let id = 400 + spoke;
+            let cost = 40 + spoke as i64 * 3;
+            flight(id, 1 + spoke, spokes + 1, cost, "20:00", "21:00")
+        }));
+        let airports = graph(spokes + 2, flights);
+        let sequential = Search::new(airports.clone());
+        let parallel = Search::new(airports).with_parallel_seeding(true);
+        for results in [1, 10, 50] {
+            let query = query(0, spokes + 1, 2, results);
+            assert_eq!(
+                ids(&parallel.find(query.clone()).unwrap()),
+                ids(&sequential.find(query).unwrap())
+            );
+        }
+    }
+
+    #[test]
+    fn layovers_are_measured_in_utc_across_zones() {
+        let airports = AirportsContainer::new();
+        airports.add_airport(testutil::airport(0));
+        airports.add_airport(Airport {
+            timezone: chrono_tz::America::New_York,
+            ..testutil::airport(1)
+        });
+        airports.add_airport(testutil::airport(2));
+        // Leaves at 08:00 UTC and lands at 10:00 UTC, 05:00 in New York.
+        airports.add_flight(FlightDTO {
+            duration_minutes: Some(120),
+            ..flight(0, 0, 1, 100, "08:00", "10:00")
+        });
+        // Departing an hour after landing, and four and a half hours after.
+        airports.add_flight(flight(1, 1, 2, 100, "06:00", "12:00"));
+        airports.add_flight(flight(2, 1, 2, 50, "09:30", "15:30"));
+        let search = Search::new(airports);
+
+        let found = search.find(query(0, 2, 2, 5)).unwrap();
+        assert_eq!(ids(&found), vec![vec![0, 2], vec![0, 1]]);
+        assert_eq!(found[1].layover_durations(), vec![Duration::minutes(60)]);
+        let short_connections = SearchQuery {
+            min_layover_minutes: Some(45),
+            max_layover_minutes: Some(120),
+            ..query(0, 2, 2, 5)
+        };
+        assert_eq!(
+            ids(&search.find(short_connections).unwrap()),
+            vec![vec![0, 1]]
+        );
+    }
+
+    #[test]
+    fn builder_builds_a_valid_query() {
+        let query = SearchQuery::builder()
+            .from(0)
+            .to(2)
+            .date(testutil::DATE)
+            .hops(2)
+            .results(3)
+            .max_cost(500)
+            .build()
+            .unwrap();
+        assert_eq!(
+            (query.from, query.to, query.hops, query.results),
+            (0, 2, 2, 3)
+        );
+        assert_eq!(query.date, testutil::DATE);
+        assert_eq!(query.max_cost, Some(500));
+        assert_eq!(query.max_layover_minutes, None);
+    }
+
+    #[test]
+    fn builder_rejects_each_invalid_field() {
+        let valid = SearchQuery::builder()
+            .date(testutil::DATE)
+            .hops(1)
+            .results(1);
+        assert_eq!(
+            valid.clone().hops(0).build().unwrap_err(),
+            QueryError::ZeroHops
+        );
+        assert_eq!(
+            valid.clone().results(0).build().unwrap_err(),
+            QueryError::ZeroResults
+        );
+        assert_eq!(
+            valid.date("14/01/2024").build().unwrap_err(),
+            QueryError::InvalidDate("14/01/2024".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_dates_are_an_error_not_a_panic() {
+        let search = Search::new(graph(2, vec![flight(0, 0, 1, 100, "08:00", "09:00")]));
+        let bad_date = SearchQuery {
+            date: "2024-13-99".to_string(),
+            ..query(0, 1, 1, 5)
+        };
+        let expected = SearchError::InvalidQuery(QueryError::InvalidDate("2024-13-99".to_string()));
+        assert_eq!(search.find(bad_date.clone()).unwrap_err(), expected);
+        assert_eq!(block_on(search.find_async(bad_date)).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn raising_max_airlines_unlocks_mixed_carrier_itineraries() {
+        let search = two_by_two();
+        let airlines = |max| SearchQuery {
+            max_airlines: Some(max),
+            ..query(0, 2, 2, 10)
+        };
+        // Two AA legs are one airline, not two.
+        assert_eq!(ids(&search.find(airlines(1)).unwrap()), vec![vec![0, 2]]);
+        assert_eq!(
+            ids(&search.find(airlines(2)).unwrap()),
+            vec![vec![0, 3], vec![0, 2], vec![1, 3], vec![1, 2]]
+        );
+    }
+
+    #[test]
+    fn stats_grow_with_hops() {
+        let search = Search::new(random_graph(7, 8, 80));
+        let stats: Vec<SearchStats> = (1..=4)
+            .map(|hops| search.find_with_stats(query(0, 7, hops, 10)).unwrap().1)
+            .collect();
+        for pair in stats.windows(2) {
+            assert!(pair[0].generated <= pair[1].generated, "{:?}", stats);
+            assert!(pair[0].expanded <= pair[1].expanded, "{:?}", stats);
+            assert!(pair[0].heap_peak <= pair[1].heap_peak, "{:?}", stats);
+        }
+        assert!(stats[0].expanded < stats[3].expanded, "{:?}", stats);
+    }
+
+    #[test]
+    fn direct_flights_match_the_general_search() {
+        let by_cost = |found: Vec<&PathState>| -> Vec<(i64, usize)> {
+            let mut found: Vec<_> = found
+                .iter()
+                .map(|x| (x.cost, x.current.flight_id))
+                .collect();
+            found.sort();
+            found
+        };
+        for seed in 0..10 {
+            let search = Search::new(random_graph(seed, 6, 60));
+            for from in 0..6 {
+                for to in (0..6).filter(|&x| x != from) {
+                    let direct = search.direct_flights(from, to, testutil::DATE);
+                    let one_hop = search.find_or_empty(query(from, to, 1, 1000));
+                    assert_eq!(ids(&one_hop), ids(&direct));
+                    let nonstop = SearchQuery {
+                        filters: FilterSet::default().hard(Constraint::MaxStops(0)),
+                        ..query(from, to, 3, 1000)
+                    };
+                    assert_eq!(ids(&search.find_or_empty(nonstop)), ids(&direct));
+                    // The general traversal finds the same nonstops, if in
+                    // another order among equal costs.
+                    let searched = search.find_or_empty(query(from, to, 3, 1000));
+                    let searched = searched.iter().filter(|x| x.path.len() == 1).collect();
+                    assert_eq!(by_cost(searched), by_cost(direct.iter().collect()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn direct_flights_apply_the_query_filters() {
+        let search = Search::new(graph(
+            2,
+            vec![
+                FlightDTO {
+                    seats: 0,
+                    ..flight(0, 0, 1, 50, "07:00", "08:00")
+                },
+                flown_by("AA", flight(1, 0, 1, 100, "08:00", "09:00")),
+                flown_by("BA", flight(2, 0, 1, 150, "08:00", "09:00")),
+                FlightDTO {
+                    cabin: Cabin::Business,
+                    ..flown_by("BA", flight(3, 0, 1, 400, "08:00", "09:00"))
+                },
+            ],
+        ));
+        let direct = search.direct_flights(0, 1, testutil::DATE);
+        assert_eq!(ids(&direct), vec![vec![1], vec![2], vec![3]]);
+        let economy = SearchQuery {
+            cabin: Some(Cabin::Economy),
+            ..query(0, 1, 1, 5)
+        };
+        assert_eq!(
+            ids(&search.find(economy.clone()).unwrap()),
+            vec![vec![1], vec![2]]
+        );
+        let not_aa = SearchQuery {
+            exclude_airlines: Some(vec!["AA".to_string()]),
+            ..economy
+        };
+        assert_eq!(ids(&search.find(not_aa).unwrap()), vec![vec![2]]);
+    }
+
+    #[test]
+    fn multi_origin_finds_the_best_start_among_origins() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 3, 500, "08:00", "12:00"),
+                flight(1, 1, 2, 100, "08:00", "09:00"),
+                flight(2, 2, 3, 100, "10:00", "11:00"),
+                // Flying between origins is never better than starting there.
+                flight(3, 0, 1, 10, "06:00", "07:00"),
+            ],
+        ));
+        let found = search
+            .find_multi_origin(vec![0, 1], 3, testutil::DATE, 3, 5)
+            .unwrap();
+        assert_eq!(ids(&found), vec![vec![1, 2], vec![0]]);
+        assert_eq!(search.itinerary(&found[0]).legs[0].from_id, 1);
+        let from_first = search.find(query(0, 3, 3, 5)).unwrap();
+        assert_eq!(ids(&from_first), vec![vec![3, 1, 2], vec![0]]);
+    }
+
+    #[test]
+    fn multi_dest_ends_at_any_target() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 0, 2, 80, "08:00", "09:00"),
+                flight(2, 0, 3, 50, "08:00", "09:00"),
+                flight(3, 3, 1, 20, "10:00", "11:00"),
+            ],
+        ));
+        let targets = HashSet::from([1, 2]);
+        let found = search
+            .find_multi_dest(0, targets, testutil::DATE, 3, 5)
+            .unwrap();
+        assert_eq!(ids(&found), vec![vec![2, 3], vec![1], vec![0]]);
+        let reached: Vec<usize> = found
+            .iter()
+            .map(|x| search.itinerary(x).destination_id)
+            .collect();
+        assert_eq!(reached, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn raising_max_trip_hours_keeps_long_itineraries() {
+        let search = Search::new(graph(
+            2,
+            vec![
+                // Lands 50 hours after the start of the travel date.
+                FlightDTO {
+                    duration_minutes: Some(30 * 60),
+                    ..flight(0, 0, 1, 100, "20:00", "02:00")
+                },
+                flight(1, 0, 1, 500, "08:00", "12:00"),
+            ],
+        ));
+        let within = |hours| SearchQuery {
+            max_trip_hours: Some(hours),
+            ..query(0, 1, 1, 5)
+        };
+        assert_eq!(ids(&search.find(query(0, 1, 1, 5)).unwrap()), vec![vec![1]]);
+        assert_eq!(
+            ids(&search.find(within(72)).unwrap()),
+            vec![vec![0], vec![1]]
+        );
+        assert_eq!(ids(&search.find(within(12)).unwrap()), vec![vec![1]]);
+        assert_eq!(
+            search.find(within(11)).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+    }
+
+    #[test]
+    fn itineraries_never_revisit_an_airport() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 1, 10, "08:00", "09:00"),
+                flight(1, 1, 2, 10, "10:00", "11:00"),
+                flight(2, 2, 1, 10, "12:00", "13:00"),
+                flight(3, 1, 3, 10, "14:00", "15:00"),
+                flight(4, 0, 3, 1000, "08:00", "12:00"),
+                // Back to the origin, and on from there more cheaply.
+                flight(5, 1, 0, 1, "10:00", "11:00"),
+                flight(6, 0, 3, 1, "12:00", "13:00"),
+            ],
+        ));
+        let found = search.find(query(0, 3, 4, 10)).unwrap();
+        // 0 -> 1 -> 2 -> 1 -> 3 and 0 -> 1 -> 0 -> 3 are cycles.
+        assert_eq!(ids(&found), vec![vec![6], vec![0, 3], vec![4]]);
+    }
+
+    #[test]
+    fn via_requires_routing_through_a_hub() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 3, 100, "10:00", "11:00"),
+                flight(2, 0, 2, 150, "08:00", "09:00"),
+                flight(3, 2, 3, 150, "10:00", "11:00"),
+            ],
+        ));
+        let via = |airports| SearchQuery {
+            via: Some(airports),
+            ..query(0, 3, 3, 1)
+        };
+        assert_eq!(
+            ids(&search.find(query(0, 3, 3, 1)).unwrap()),
+            vec![vec![0, 1]]
+        );
+        assert_eq!(ids(&search.find(via(vec![2])).unwrap()), vec![vec![2, 3]]);
+        // The endpoints are always visited.
+        assert_eq!(
+            ids(&search.find(via(vec![0, 3])).unwrap()),
+            vec![vec![0, 1]]
+        );
+        assert_eq!(
+            search.find(via(vec![1, 2])).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+    }
+
+    #[test]
+    fn failures_say_why_there_are_no_itineraries() {
+        // 2 exists but nothing flies there.
+        let search = Search::new(graph(3, vec![flight(0, 0, 1, 100, "08:00", "09:00")]));
+        assert_eq!(
+            search.find(query(7, 1, 1, 5)).unwrap_err(),
+            SearchError::UnknownOrigin
+        );
+        assert_eq!(
+            search.find(query(0, 7, 1, 5)).unwrap_err(),
+            SearchError::UnknownDestination
+        );
+        assert_eq!(
+            search.find(query(0, 2, 1, 5)).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+        assert_eq!(ids(&search.find(query(0, 1, 1, 5)).unwrap()), vec![vec![0]]);
+        // The old behaviour, for callers that do not care why.
+        assert!(search.find_or_empty(query(0, 7, 1, 5)).is_empty());
+        assert!(search.find_or_empty(query(0, 2, 1, 5)).is_empty());
+    }
+
+    #[test]
+    fn find_pareto_excludes_the_dominated_itinerary() {
+        // `results` does not cap the frontier.
+        let frontier = trade_offs().find_pareto(query(0, 1, 1, 1));
+        let flights: Vec<Vec<usize>> = frontier
+            .iter()
+            .map(|x| x.legs.iter().map(|leg| leg.flight_id).collect())
+            .collect();
+        assert_eq!(flights, vec![vec![0], vec![1]]);
+        let trade_off: Vec<_> = frontier
+            .iter()
+            .map(|x| (x.total_cost, x.total_duration.num_hours()))
+            .collect();
+        assert_eq!(trade_off, vec![(100, 8), (300, 1)]);
+    }
+
+    #[test]
+    fn excluding_the_direct_flight_forces_a_connection() {
+        let search = Search::new(graph(
+            4,
+            vec![
+                flight(0, 0, 3, 100, "08:00", "10:00"),
+                flight(1, 0, 1, 100, "08:00", "09:00"),
+                flight(2, 1, 3, 100, "10:00", "11:00"),
+                flight(3, 0, 2, 150, "08:00", "09:00"),
+                flight(4, 2, 3, 150, "10:00", "11:00"),
+            ],
+        ));
+        let cancelled = SearchQuery {
+            exclude_flights: Some(HashSet::from([0])),
+            ..query(0, 3, 3, 1)
+        };
+        assert_eq!(ids(&search.find(query(0, 3, 3, 1)).unwrap()), vec![vec![0]]);
+        assert_eq!(
+            ids(&search.find(cancelled.clone()).unwrap()),
+            vec![vec![1, 2]]
+        );
+        let closed = SearchQuery {
+            exclude_airports: Some(HashSet::from([1])),
+            ..cancelled
+        };
+        assert_eq!(ids(&search.find(closed).unwrap()), vec![vec![3, 4]]);
+    }
+
+    #[test]
+    fn read_view_matches_locked_traversal_under_concurrency() {
+        let airports = random_graph(3, 12, 600);
+        let locked = Search::new(airports.clone());
+        let snapshot = Search::new(airports.clone()).with_read_view(true);
+        let queries: Vec<SearchQuery> = (1..12).map(|to| query(0, to, 3, 5)).collect();
+        // Every query from eight threads at once; returns each thread's
+        // results. Timings are compared in the `concurrent` benchmark.
+        let run = |search: &Search| -> Vec<Vec<Vec<Vec<usize>>>> {
+            std::thread::scope(|scope| {
+                let threads: Vec<_> = (0..8)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            queries
+                                .iter()
+                                .map(|x| ids(&search.find_or_empty(x.clone())))
+                                .collect()
+                        })
+                    })
+                    .collect();
+                threads.into_iter().map(|x| x.join().unwrap()).collect()
+            })
+        };
+
+        let by_lock = run(&locked);
+        assert!(by_lock.iter().all(|x| *x == by_lock[0]));
+        assert!(by_lock[0].iter().any(|x| !x.is_empty()));
+        assert_eq!(run(&snapshot), by_lock);
+
+        // Connecting legs are read from the view, unlike first legs, so
+        // removing one through yet another handle must rebuild it.
+        let connection = by_lock[0].iter().flatten().find(|x| x.len() > 1).unwrap()[1];
+        airports.clone().remove_flight(connection);
+        let after = run(&locked);
+        assert_ne!(after, by_lock);
+        assert_eq!(run(&snapshot), after);
+    }
+
+    #[test]
+    fn cabin_filter_picks_the_itinerary_for_that_cabin() {
+        let business = |flight| FlightDTO {
+            cabin: Cabin::Business,
+            ..flight
+        };
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "10:00"),
+                business(flight(1, 0, 1, 500, "08:00", "10:00")),
+                business(flight(2, 0, 2, 150, "08:00", "09:00")),
+                business(flight(3, 2, 1, 150, "10:00", "11:00")),
+            ],
+        ));
+        let in_cabin = |cabin| SearchQuery {
+            cabin: Some(cabin),
+            ..query(0, 1, 2, 5)
+        };
+        let economy = search.find(in_cabin(Cabin::Economy)).unwrap();
+        assert_eq!(ids(&economy), vec![vec![0]]);
+        let business = search.find(in_cabin(Cabin::Business)).unwrap();
+        assert_eq!(ids(&business), vec![vec![2, 3], vec![1]]);
+        assert!(business[0].cost > economy[0].cost);
+        assert_eq!(
+            search.find(in_cabin(Cabin::First)).unwrap_err(),
+            SearchError::NoRouteFound
+        );
+    }
+
+    #[test]
+    fn mixed_currency_legs_sum_in_the_base_currency() {
+        let euros = |flight| FlightDTO {
+            currency: "EUR".to_string(),
+            ..flight
+        };
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                euros(flight(1, 1, 2, 100, "10:00", "11:00")),
+                flight(2, 0, 2, 205, "08:00", "10:00"),
+                // The lowest fare on its face, but the dearest in dollars.
+                euros(flight(3, 0, 2, 195, "08:00", "10:00")),
+            ],
+        ))
+        .with_currency_converter(Arc::new(StaticRates::new("USD").with_rate("EUR", 1.1)));
+        let found = search.find(query(0, 2, 2, 5)).unwrap();
+        assert_eq!(ids(&found), vec![vec![2], vec![0, 1], vec![3]]);
+        let totals: Vec<i64> = found.iter().map(|x| x.cost).collect();
+        assert_eq!(totals, vec![205, 210, 215]);
+    }
+
+    #[test]
+    fn legs_in_currencies_without_a_rate_are_never_flown() {
+        let yen = |flight| FlightDTO {
+            currency: "JPY".to_string(),
+            ..flight
+        };
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                yen(flight(1, 1, 2, 1, "10:00", "11:00")),
+                flight(2, 1, 2, 150, "10:00", "11:00"),
+                yen(flight(3, 0, 2, 1, "08:00", "10:00")),
+                flight(4, 0, 2, 300, "08:00", "10:00"),
+            ],
+        ));
+        assert_eq!(
+            ids(&search.find(query(0, 2, 2, 5)).unwrap()),
+            vec![vec![3], vec![0, 1], vec![0, 2], vec![4]]
+        );
+        let converted =
+            search.with_currency_converter(Arc::new(StaticRates::new("USD").with_rate("EUR", 1.1)));
+        assert_eq!(
+            ids(&converted.find(query(0, 2, 2, 5)).unwrap()),
+            vec![vec![0, 2], vec![4]]
+        );
+        assert_eq!(
+            ids(&converted.direct_flights(0, 2, testutil::DATE)),
+            vec![vec![4]]
+        );
+    }
+
+    #[test]
+    fn find_ranked_orders_by_the_comparator() {
+        let search = Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 2, 100, "08:00", "18:00"),
+                flight(1, 0, 2, 300, "08:00", "09:00"),
+                flight(2, 0, 1, 50, "08:00", "09:00"),
+                flight(3, 1, 2, 100, "12:00", "13:00"),
+            ],
+        ));
+        let earliest_arrival =
+            |a: &PathState, b: &PathState| a.current.arrive_utc.cmp(&b.current.arrive_utc);
+        assert_eq!(
+            ids(&search.find(query(0, 2, 2, 2)).unwrap()),
+            vec![vec![0], vec![2, 3]]
+        );
+        // The fast, dear nonstop is ranked first though it is not among the
+        // two cheapest.
+        assert_eq!(
+            ids(&search
+                .find_ranked(query(0, 2, 2, 2), earliest_arrival)
+                .unwrap()),
+            vec![vec![1], vec![2, 3]]
+        );
+    }
+
+    #[test]
+    fn accessors_handle_short_and_empty_paths() {
+        let airports = graph(2, vec![flight(0, 0, 1, 100, "08:00", "08:00")]);
+        let edge = airports
+            .flights_container
+            .read()
+            .unwrap()
+            .get_flight(0)
+            .unwrap();
+        // A single leg that lands the minute it leaves.
+        let single = PathState::start(&edge, edge.cost);
+        assert_eq!(single.total_duration(), Duration::zero());
+        assert_eq!(single.layover_durations(), vec![]);
+        assert_eq!(single.longest_layover(), Duration::zero());
+        assert_eq!(single.to_itinerary().num_stops, 0);
+        assert_eq!(single.readable_path().len(), 3);
+
+        // Never built by a search, but the accessors must not panic on it.
+        let empty = PathState {
+            path: vec![],
+            ..single.clone()
+        };
+        assert!(empty.legs().is_empty());
+        assert_eq!(empty.total_duration(), Duration::zero());
+        assert_eq!(empty.departure_date(), single.departure_date());
+        assert_eq!(empty.origin_offset(), Duration::zero());
+        assert_eq!(empty.layover_durations(), vec![]);
+        assert_eq!(
+            empty.readable_path(),
+            vec!["Total cost: 100", "Total duration: 0h 0m"]
+        );
+        let itinerary = empty.to_itinerary();
+        assert_eq!((itinerary.legs.len(), itinerary.num_stops), (0, 0));
+        assert_eq!(itinerary.total_cost, 100);
+    }
+
+    #[test]
+    fn changes_through_another_handle_invalidate_the_cache() {
+        let airports = graph(
+            3,
+            vec![
+                flight(0, 0, 1, 100, "08:00", "09:00"),
+                flight(1, 1, 2, 100, "10:00", "11:00"),
+            ],
+        );
+        let search = Search::new(airports.clone()).with_cache(4);
+        assert_eq!(
+            ids(&search.find(query(0, 2, 2, 5)).unwrap()),
+            vec![vec![0, 1]]
+        );
+
+        airports.remove_flight(1);
+        assert_eq!(
+            search.find(query(0, 2, 2, 5)).unwrap_err(),
+            SearchError::NoRouteFound
+        );
     }
 }
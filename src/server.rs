@@ -0,0 +1,195 @@
+use std::error::Error;
+
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use tokio::net::TcpListener;
+
+use crate::itinerary::{group_fare_options, Itinerary};
+use crate::mods::tpl::{Tpl, TplRenderer};
+use crate::search::{Search, SearchError, SearchQuery};
+
+const DEFAULT_HOPS: usize = 3;
+const DEFAULT_RESULTS: usize = 10;
+
+/// Query string accepted by `GET /search`, e.g.
+/// `/search?from=14576&to=14689&date=2024-01-14&hops=3&results=10`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SearchParams {
+    pub from: usize,
+    pub to: usize,
+    /// "YYYY-MM-DD".
+    pub date: String,
+    pub hops: Option<usize>,
+    pub results: Option<usize>,
+}
+
+/// Routes for the HTTP API: `GET /search` returns itineraries as JSON,
+/// `GET /search.html` the same itineraries as a page, and `GET /healthz`
+/// answers while the server is up.
+pub fn router(search: Search) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/search", get(search_handler))
+        .route("/search.html", get(search_page_handler))
+        .with_state(search)
+}
+
+/// Serves `router` on `addr` until the process exits.
+pub async fn serve(search: Search, addr: &str) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(addr = %listener.local_addr()?, "listening");
+    axum::serve(listener, router(search)).await?;
+    Ok(())
+}
+
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+async fn itineraries(search: &Search, params: SearchParams) -> Result<Vec<Itinerary>, SearchError> {
+    let query = SearchQuery::builder()
+        .from(params.from)
+        .to(params.to)
+        .date(&params.date)
+        .hops(params.hops.unwrap_or(DEFAULT_HOPS))
+        .results(params.results.unwrap_or(DEFAULT_RESULTS))
+        .build()?;
+    let found = match search.find_async(query).await {
+        Err(SearchError::NoRouteFound) => vec![],
+        found => found?,
+    };
+    let itineraries = found.iter().map(|state| search.itinerary(state)).collect();
+    Ok(group_fare_options(itineraries))
+}
+
+async fn search_handler(
+    State(search): State<Search>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    match itineraries(&search, params).await {
+        Ok(itineraries) => Json(itineraries).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+// 404 when an airport does not exist, 503 when the search queue timed
+// out, 500 when the search itself failed, 400 for a malformed query.
+fn error_response(e: SearchError) -> Response {
+    let status = match e {
+        SearchError::UnknownOrigin | SearchError::UnknownDestination => StatusCode::NOT_FOUND,
+        SearchError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+        SearchError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, e.to_string()).into_response()
+}
+
+async fn search_page_handler(
+    State(search): State<Search>,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    match itineraries(&search, params).await {
+        Ok(itineraries) => {
+            let renderer = TplRenderer { messages: vec![] };
+            Html(renderer.render_results(&itineraries)).into_response()
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, Request};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::currency::CurrencyConverter;
+    use crate::testutil::{flight, graph};
+
+    fn search() -> Search {
+        Search::new(graph(
+            3,
+            vec![
+                flight(0, 0, 1, 50, "08:00", "09:00"),
+                flight(1, 1, 2, 50, "10:00", "11:00"),
+                flight(2, 0, 2, 300, "08:00", "10:00"),
+            ],
+        ))
+    }
+
+    async fn get(uri: &str) -> (StatusCode, Option<String>, String) {
+        get_from(search(), uri).await
+    }
+
+    async fn get_from(search: Search, uri: &str) -> (StatusCode, Option<String>, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router(search).oneshot(request).await.unwrap();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|x| x.to_str().unwrap().to_string());
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn search_returns_itineraries_as_json() {
+        let (status, content_type, body) = get("/search?from=0&to=2&date=2024-01-14").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        let itineraries: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let costs: Vec<_> = itineraries
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["total_cost"].as_i64().unwrap())
+            .collect();
+        assert_eq!(costs, vec![100, 300]);
+    }
+
+    #[tokio::test]
+    async fn search_reports_bad_queries_with_a_status() {
+        let (status, _, _) = get("/search?from=0&to=7&date=2024-01-14").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, body) = get("/search?from=0&to=2&date=2024-13-99").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("2024-13-99"), "{}", body);
+    }
+
+    // Fails every search that converts a fare.
+    struct Broken;
+
+    impl CurrencyConverter for Broken {
+        fn to_base(&self, _amount: i64, _from: &str) -> Option<i64> {
+            panic!("no rates");
+        }
+
+        fn base_to(&self, _amount: i64, _to: &str) -> Option<i64> {
+            panic!("no rates");
+        }
+    }
+
+    #[tokio::test]
+    async fn search_reports_failed_searches_as_server_errors() {
+        let broken = search().with_currency_converter(std::sync::Arc::new(Broken));
+        let (status, _, body) = get_from(broken, "/search?from=0&to=2&date=2024-01-14").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body, "search failed unexpectedly");
+    }
+
+    #[tokio::test]
+    async fn healthz_answers_ok() {
+        let (status, _, body) = get("/healthz").await;
+        assert_eq!((status, body.as_str()), (StatusCode::OK, "ok"));
+    }
+}
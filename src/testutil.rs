@@ -0,0 +1,98 @@
+//! Small graphs shared by the unit tests.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::airport::{Airport, AirportsContainer};
+use crate::flight::{Cabin, FlightDTO, UNLIMITED_SEATS};
+use crate::search::{PathState, SearchQuery};
+
+/// Day every fixture flight departs on.
+pub const DATE: &str = "2024-01-14";
+
+/// A UTC airport at the origin of the map, named "A<id>".
+pub fn airport(id: usize) -> Airport {
+    Airport {
+        id,
+        name: format!("A{}", id),
+        latitude: 0.0,
+        longitude: 0.0,
+        timezone: chrono_tz::Tz::UTC,
+        outgoing: BTreeMap::new(),
+    }
+}
+
+/// Economy flight `id` on `DATE` priced in USD, with times as "HH:MM".
+pub fn flight(
+    id: usize,
+    from: usize,
+    to: usize,
+    cost: i64,
+    depart: &str,
+    arrive: &str,
+) -> FlightDTO {
+    FlightDTO {
+        flight_id: id,
+        from,
+        to,
+        cost,
+        currency: "USD".to_string(),
+        distance: 100,
+        cabin: Cabin::Economy,
+        seats: UNLIMITED_SEATS,
+        airline: String::new(),
+        flight_number: String::new(),
+        codeshares: vec![],
+        departure_date: format!("{} {}:00", DATE, depart),
+        arrival_date: format!("{} {}:00", DATE, arrive),
+        duration_minutes: None,
+    }
+}
+
+/// `flight` flown by `airline`.
+pub fn flown_by(airline: &str, flight: FlightDTO) -> FlightDTO {
+    FlightDTO {
+        airline: airline.to_string(),
+        ..flight
+    }
+}
+
+/// Airports `0..airports` joined by `flights`.
+pub fn graph(airports: usize, flights: Vec<FlightDTO>) -> AirportsContainer {
+    let container = AirportsContainer::new();
+    for id in 0..airports {
+        container.add_airport(airport(id));
+    }
+    for flight in flights {
+        container.add_flight(flight);
+    }
+    container
+}
+
+/// Cheapest-first query from `from` to `to` on `DATE`.
+pub fn query(from: usize, to: usize, hops: usize, results: usize) -> SearchQuery {
+    SearchQuery {
+        from,
+        to,
+        date: DATE.to_string(),
+        hops,
+        results,
+        ..Default::default()
+    }
+}
+
+/// Writes `contents` to a file under the system temp directory, named
+/// after `name` and this process so parallel test runs don't collide.
+pub fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rust-test-{}-{}", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Flight ids of each result, in order.
+pub fn ids(results: &[PathState]) -> Vec<Vec<usize>> {
+    results
+        .iter()
+        .map(|x| x.legs().iter().map(|leg| leg.flight_id).collect())
+        .collect()
+}